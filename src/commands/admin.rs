@@ -0,0 +1,1684 @@
+//! /renamer admin and the owner-only usage command
+
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Duration;
+
+use poise::serenity_prelude as serenity;
+use poise::serenity_prelude::http::routing::Route;
+use poise::serenity_prelude::{
+    AttachmentType, ButtonStyle, CacheHttp, GatewayIntents, Http, Member, Mentionable, Permissions,
+    Role, RoleId, User,
+};
+use tracing::{error, warn};
+
+use super::help::runnable_commands;
+use super::history::{describe_provenance, provenance, Provenance};
+use super::rename::LookupMode;
+use super::sweep::sweep;
+use super::{
+    check_members_intent, check_set_up, confirm, guild_members, guild_permissions,
+    highest_role_position, rate_limit_wait, role_by_name, staff_roles, BoosterPerk,
+    ComponentAction, Context, Error, Progress,
+};
+use crate::preset::{self, Preset};
+use crate::random_name;
+use crate::response::Response;
+use crate::services::migrations;
+use crate::services::storage::AppRole::*;
+use crate::services::storage::Setting::*;
+use crate::services::storage::{
+    unix_now, AppRole, ConsentCampaign, HistoryEntry, HistoryKind, Storage,
+};
+use crate::services::validation::{self, load_pipeline, validate_nickname, Mode, Outcome, Step};
+
+#[poise::command(
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    subcommands(
+        "set_roles",
+        "cleanup_roles",
+        "set_transliteration",
+        "set_random_names",
+        "set_match_threshold",
+        "set_lookup_mode",
+        "list_managed",
+        "set_self_service",
+        "set_observe_mode",
+        "set_target_rules",
+        "set_age_gates",
+        "validation",
+        "sweep",
+        "test_nickname",
+        "doctor",
+        "disable_command",
+        "enable_command",
+        "allow_role",
+        "set_staff_role",
+        "set_booster_perk",
+        "config_history",
+        "config_rollback",
+        "preset",
+        "consent_campaign",
+        "import_current"
+    )
+)]
+pub(super) async fn admin(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Properties given to a server role when the bot has to create it
+#[derive(Default)]
+struct RoleProperties {
+    colour: Option<u64>,
+    hoist: bool,
+    mentionable: bool,
+    position: Option<u8>,
+    permissions: Option<Permissions>,
+}
+
+fn parse_colour(colour: &str) -> Option<u64> {
+    let hex = colour.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u64::from_str_radix(hex, 16).ok()
+}
+
+async fn set_role(
+    app_role: AppRole,
+    ctx: &Context<'_>,
+    role_name: &str,
+    properties: &RoleProperties,
+) -> Result<String, Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    let http = ctx.http();
+
+    // Check for existing role in server; create new one if absent
+    let (role_set_msg, role_id) = match role_by_name!(guild_id, http, role_name) {
+        Some(role) => (
+            format!("Using existing server role {}.", role_name),
+            role.id,
+        ),
+        None => {
+            let new_role_id = guild_id
+                .create_role(http, |r| {
+                    r.name(role_name)
+                        .hoist(properties.hoist)
+                        .mentionable(properties.mentionable);
+                    if let Some(colour) = properties.colour {
+                        r.colour(colour);
+                    }
+                    if let Some(position) = properties.position {
+                        r.position(position);
+                    }
+                    if let Some(permissions) = properties.permissions {
+                        r.permissions(permissions);
+                    }
+                    r
+                })
+                .await?
+                .id;
+            storage.created_roles.insert(&guild_id, &new_role_id)?;
+            (
+                format!("Created new server role {}.", role_name),
+                new_role_id,
+            )
+        }
+    };
+
+    // Role DB operations
+    let db_msg = match storage.roles.get(app_role, &guild_id)? {
+        Some(stored_role_id) if stored_role_id == role_id => {
+            format!(
+                "{} role is already set to {}; no change made.",
+                app_role, role_name
+            )
+        }
+        _ => {
+            if let Some(previous_role_id) = storage.set_role(app_role, &guild_id, &role_id)? {
+                format!(
+                    "{} role was changed from {} to {}.",
+                    app_role,
+                    previous_role_id.mention(),
+                    role_name
+                )
+            } else {
+                format!("{} role was set to {}.", app_role, role_name)
+            }
+        }
+    };
+
+    // Compose message
+    let msg = format!("{}\n{}", db_msg, role_set_msg);
+
+    Ok(msg)
+}
+
+#[poise::command(
+    slash_command,
+    required_bot_permissions = "MANAGE_ROLES",
+    category = "Admin"
+)]
+#[allow(clippy::too_many_arguments)]
+async fn set_roles(
+    ctx: Context<'_>,
+    #[description = "Name of the Renamer role; defaults to one in the server's language"]
+    renamer_role: Option<String>,
+    #[description = "Name of the Allow role; defaults to one in the server's language"]
+    allow_role: Option<String>,
+    #[description = "Colour of a newly created Renamer role as a hex code, e.g. #ff8800"]
+    renamer_color: Option<String>,
+    #[description = "Show a newly created Renamer role separately in the member list"]
+    renamer_hoist: Option<bool>,
+    #[description = "Let everyone mention a newly created Renamer role by name"]
+    renamer_mentionable: Option<bool>,
+    #[description = "Position of a newly created Renamer role in the role list"]
+    renamer_position: Option<u8>,
+    #[description = "Grant Manage Nicknames to a newly created Renamer role"]
+    grant_manage_nicknames: Option<bool>,
+    #[description = "Colour of a newly created Allow role as a hex code, e.g. #ff8800"]
+    allow_color: Option<String>,
+    #[description = "Show a newly created Allow role separately in the member list"]
+    allow_hoist: Option<bool>,
+    #[description = "Let everyone mention a newly created Allow role by name"]
+    allow_mentionable: Option<bool>,
+    #[description = "Position of a newly created Allow role in the role list"]
+    allow_position: Option<u8>,
+) -> Result<(), Error> {
+    for colour in [&renamer_color, &allow_color].into_iter().flatten() {
+        if parse_colour(colour).is_none() {
+            Response::error(format!(
+                "{} is not a valid colour. Use a hex code like #ff8800.",
+                colour
+            ))
+            .send(ctx)
+            .await?;
+            return Ok(());
+        }
+    }
+
+    let renamer_properties = RoleProperties {
+        colour: renamer_color.as_deref().and_then(parse_colour),
+        hoist: renamer_hoist.unwrap_or(false),
+        mentionable: renamer_mentionable.unwrap_or(false),
+        position: renamer_position,
+        permissions: grant_manage_nicknames
+            .unwrap_or(false)
+            .then_some(Permissions::MANAGE_NICKNAMES),
+    };
+    let allow_properties = RoleProperties {
+        colour: allow_color.as_deref().and_then(parse_colour),
+        hoist: allow_hoist.unwrap_or(false),
+        mentionable: allow_mentionable.unwrap_or(false),
+        position: allow_position,
+        ..Default::default()
+    };
+
+    let locale = ctx
+        .guild()
+        .map(|guild| guild.preferred_locale)
+        .unwrap_or_default();
+    let renamer_role = renamer_role.unwrap_or_else(|| Renamer.default_name(&locale).into());
+    let allow_role = allow_role.unwrap_or_else(|| Allow.default_name(&locale).into());
+
+    let renamer_msg = set_role(Renamer, &ctx, &renamer_role, &renamer_properties).await?;
+    let allow_msg = set_role(Allow, &ctx, &allow_role, &allow_properties).await?;
+
+    ctx.send(|m| {
+        m.ephemeral(true).embed(|e| {
+            e.title("set_roles")
+                .field("Renamer role", renamer_msg, false)
+                .field("Allow role", allow_msg, false)
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Delete server roles created by this app that are no longer configured or held by anyone
+#[poise::command(
+    slash_command,
+    required_bot_permissions = "MANAGE_ROLES",
+    category = "Admin"
+)]
+async fn cleanup_roles(ctx: Context<'_>) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    let http = ctx.http();
+
+    // Fetching every member to check who holds the roles can take a while
+    ctx.defer_ephemeral().await?;
+
+    let server_roles = guild_id.roles(http).await?;
+    let created_role_ids = storage.created_roles.list(&guild_id)?;
+
+    // Forget roles that were already deleted by hand
+    for role_id in created_role_ids
+        .iter()
+        .filter(|role_id| !server_roles.contains_key(*role_id))
+    {
+        storage.created_roles.remove(&guild_id, role_id)?;
+    }
+
+    let configured_ids = [Renamer, Allow]
+        .into_iter()
+        .map(|app_role| storage.roles.get(app_role, &guild_id))
+        .collect::<Result<Vec<_>, _>>()?;
+    if !check_members_intent(&ctx).await? {
+        return Ok(());
+    }
+    let members = guild_members(&ctx).await?;
+
+    let unused_roles: Vec<&Role> = created_role_ids
+        .iter()
+        .filter_map(|role_id| server_roles.get(role_id))
+        .filter(|role| {
+            let configured = configured_ids.iter().flatten().any(|id| *id == role.id);
+            let held = members.iter().any(|member| member.roles.contains(&role.id));
+            !configured && !held
+        })
+        .collect();
+
+    if unused_roles.is_empty() {
+        Response::info("No unused roles created by renamer were found.")
+            .send(ctx)
+            .await?;
+        return Ok(());
+    }
+
+    let role_list = unused_roles
+        .iter()
+        .map(|role| role.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let prompt = format!(
+        "Delete these unused roles created by renamer? {}",
+        role_list
+    );
+    if !confirm(&ctx, &prompt).await? {
+        return Ok(());
+    }
+
+    let mut progress = Progress::start(ctx, "Deleting roles").await?;
+    for (i, role) in unused_roles.iter().enumerate() {
+        progress.update(i, unused_roles.len()).await;
+        guild_id.delete_role(http, role.id).await?;
+        storage.created_roles.remove(&guild_id, &role.id)?;
+    }
+
+    progress
+        .finish(&format!(
+            "Deleted {} role(s): {}",
+            unused_roles.len(),
+            role_list
+        ))
+        .await?;
+
+    Ok(())
+}
+
+/// Automatically transliterate non-Latin nicknames given with /rename
+#[poise::command(slash_command, category = "Admin")]
+async fn set_transliteration(ctx: Context<'_>, enabled: bool) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+
+    storage.set_setting(AutoTransliterate, &guild_id, &enabled.to_string())?;
+
+    let msg = if enabled {
+        "Nicknames given with /rename will now be transliterated to Latin characters."
+    } else {
+        "Nicknames given with /rename will no longer be transliterated."
+    };
+    Response::success(msg).send(ctx).await?;
+
+    Ok(())
+}
+
+/// Configure how /renamer random generates nicknames
+#[poise::command(slash_command, category = "Admin")]
+async fn set_random_names(
+    ctx: Context<'_>,
+    #[description = "Comma-separated words whose syllables random names are built from"]
+    seed_words: Option<String>,
+    #[description = "Fewest syllables in a random name"]
+    #[min = 1]
+    #[max = 8]
+    min_syllables: Option<u8>,
+    #[description = "Most syllables in a random name"]
+    #[min = 1]
+    #[max = 8]
+    max_syllables: Option<u8>,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+
+    if let Some(seed_words) = &seed_words {
+        storage.set_setting(RandomSeedWords, &guild_id, seed_words)?;
+    }
+    if let Some(min_syllables) = min_syllables {
+        storage.set_setting(RandomMinSyllables, &guild_id, &min_syllables.to_string())?;
+    }
+    if let Some(max_syllables) = max_syllables {
+        storage.set_setting(RandomMaxSyllables, &guild_id, &max_syllables.to_string())?;
+    }
+
+    let msg = format!(
+        "Random names use seed words: {}\nSyllables: {} to {}",
+        storage
+            .settings
+            .get(RandomSeedWords, &guild_id)?
+            .unwrap_or_else(|| "(built-in sounds)".into()),
+        storage
+            .settings
+            .get(RandomMinSyllables, &guild_id)?
+            .unwrap_or_else(|| random_name::DEFAULT_MIN_SYLLABLES.to_string()),
+        storage
+            .settings
+            .get(RandomMaxSyllables, &guild_id)?
+            .unwrap_or_else(|| random_name::DEFAULT_MAX_SYLLABLES.to_string()),
+    );
+    Response::success(msg).send(ctx).await?;
+
+    Ok(())
+}
+
+/// Set how closely /rename's username must match a member before it renames without asking
+#[poise::command(slash_command, category = "Admin")]
+async fn set_match_threshold(
+    ctx: Context<'_>,
+    #[description = "Percentage of the member's name to match; 0 never asks, 100 asks unless exact"]
+    #[max = 100]
+    percent: u8,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+
+    storage.set_setting(MatchThreshold, &guild_id, &percent.to_string())?;
+
+    Response::success(format!(
+        "/rename will ask for confirmation when a search matches less than {}% of a name.",
+        percent
+    ))
+    .send(ctx)
+    .await?;
+
+    Ok(())
+}
+
+/// Choose how /rename's username finds the member to rename
+#[poise::command(slash_command, category = "Admin")]
+async fn set_lookup_mode(
+    ctx: Context<'_>,
+    #[description = "How to match members"] mode: LookupMode,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+
+    storage.set_setting(MemberLookup, &guild_id, mode.label())?;
+
+    let msg = match mode {
+        LookupMode::Prefix => {
+            "/rename will match members whose username, nickname or display name starts with `username`."
+        }
+        LookupMode::ExactUsername => "/rename will only match a member's exact username.",
+        LookupMode::ExactDisplayName => {
+            "/rename will only match the exact name a member is shown by in this server."
+        }
+        LookupMode::Fuzzy => {
+            "/rename will match the members whose names contain `username` most closely."
+        }
+        LookupMode::Id => "/rename will only accept a user ID or mention.",
+    };
+    Response::success(msg).send(ctx).await?;
+
+    Ok(())
+}
+
+/// Members listed by /renamer admin list_managed
+const LIST_MANAGED_LIMIT: usize = 50;
+
+/// List members whose current nickname was set through renamer
+#[poise::command(slash_command, category = "Admin")]
+async fn list_managed(ctx: Context<'_>) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    let latest = storage.history.latest_for_guild(&guild_id)?;
+    if !check_members_intent(&ctx).await? {
+        return Ok(());
+    }
+
+    let mut managed: Vec<(u64, String)> = guild_members(&ctx)
+        .await?
+        .iter()
+        .filter_map(
+            |member| match provenance(member, latest.get(&member.user.id.0)) {
+                provenance @ Provenance::Managed(_, timestamp) => Some((
+                    timestamp,
+                    format!(
+                        "{}: {} ({})",
+                        member.mention(),
+                        member.nick.as_deref().unwrap_or_default(),
+                        describe_provenance(&provenance)
+                    ),
+                )),
+                _ => None,
+            },
+        )
+        .collect();
+
+    if managed.is_empty() {
+        Response::info("No member's current nickname was set through renamer.")
+            .send(ctx)
+            .await?;
+        return Ok(());
+    }
+    managed.sort_by(|a, b| b.0.cmp(&a.0));
+
+    let total = managed.len();
+    let mut lines: Vec<String> = managed
+        .into_iter()
+        .take(LIST_MANAGED_LIMIT)
+        .map(|(_, line)| line)
+        .collect();
+    if total > LIST_MANAGED_LIMIT {
+        lines.push(format!(
+            "…and {} more; export a member's history with /renamer history.",
+            total - LIST_MANAGED_LIMIT
+        ));
+    }
+    Response::info(format!(
+        "{} member(s) have a nickname set through renamer, newest first:\n{}",
+        total,
+        lines.join("\n")
+    ))
+    .send(ctx)
+    .await?;
+
+    Ok(())
+}
+
+/// Record every member's current nickname so history covers nicknames set before renamer
+#[poise::command(slash_command, category = "Admin")]
+async fn import_current(ctx: Context<'_>) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    if !check_members_intent(&ctx).await? {
+        return Ok(());
+    }
+
+    let mut progress = Progress::start(ctx, "Importing nicknames").await?;
+    let members = guild_members(&ctx).await?;
+
+    let mut imported = 0;
+    for (i, member) in members.iter().enumerate() {
+        progress.update(i, members.len()).await;
+
+        // Members with history already have a better starting point than a baseline
+        if member.user.bot
+            || !storage
+                .history
+                .for_member(&guild_id, &member.user.id)?
+                .is_empty()
+        {
+            continue;
+        }
+        storage.history.insert(HistoryEntry {
+            id: 0,
+            guild_id: guild_id.0,
+            user_id: member.user.id.0,
+            actor_id: None,
+            previous_nickname: None,
+            nickname: member.nick.clone(),
+            timestamp: unix_now(),
+            kind: HistoryKind::Baseline,
+        })?;
+        imported += 1;
+    }
+
+    progress
+        .finish(&format!(
+            "Recorded the current nickname of {} member(s) as their baseline.",
+            imported
+        ))
+        .await?;
+
+    Ok(())
+}
+
+/// Let members change their own nickname with /renamer request without approval
+#[poise::command(slash_command, category = "Admin")]
+async fn set_self_service(ctx: Context<'_>, enabled: bool) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+
+    storage.set_setting(SelfService, &guild_id, &enabled.to_string())?;
+
+    let msg = if enabled {
+        "Nickname requests will now be applied without approval."
+    } else {
+        "Nickname requests will now need approval from a renamer."
+    };
+    Response::success(msg).send(ctx).await?;
+
+    Ok(())
+}
+
+/// Refuse to rename accounts or members that are too new
+#[poise::command(slash_command, category = "Admin")]
+async fn set_age_gates(
+    ctx: Context<'_>,
+    #[description = "Minimum account age in hours; 0 allows any"] account_hours: u32,
+    #[description = "Minimum time in the server in hours; 0 allows any"] member_hours: u32,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+
+    storage.set_setting(MinAccountAgeHours, &guild_id, &account_hours.to_string())?;
+    storage.set_setting(MinMemberAgeHours, &guild_id, &member_hours.to_string())?;
+
+    let describe = |hours: u32, what: &str| match hours {
+        0 => format!("{} of any age can be renamed.", what),
+        hours => format!("{} younger than {} hour(s) won't be renamed.", what, hours),
+    };
+    Response::success(format!(
+        "{}\n{}",
+        describe(account_hours, "Accounts"),
+        describe(member_hours, "Members")
+    ))
+    .send(ctx)
+    .await?;
+
+    Ok(())
+}
+
+/// Check renames without applying them, to trial the configuration
+#[poise::command(slash_command, category = "Admin")]
+async fn set_observe_mode(ctx: Context<'_>, enabled: bool) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+
+    storage.set_setting(ObserveMode, &guild_id, &enabled.to_string())?;
+
+    let msg = if enabled {
+        "Renames will be checked as usual but not applied; each reply says what would have happened."
+    } else {
+        "Renames will now be applied."
+    };
+    Response::success(msg).send(ctx).await?;
+
+    Ok(())
+}
+
+/// Control whether members of one role may rename members of another
+#[poise::command(slash_command, category = "Admin")]
+async fn set_target_rules(
+    ctx: Context<'_>,
+    #[description = "Role whose members the rule applies to"] actor_role: Role,
+    #[description = "Role whose members they may or may not rename"] target_role: Role,
+    #[description = "Whether actor_role members may rename target_role members"] allowed: bool,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+
+    let mut protected_role_ids = storage.target_rules.get(&guild_id, &actor_role.id)?;
+    protected_role_ids.retain(|role_id| *role_id != target_role.id);
+    if !allowed {
+        protected_role_ids.push(target_role.id);
+    }
+    storage.set_target_rules(&guild_id, &actor_role.id, &protected_role_ids)?;
+
+    let protected_list = if protected_role_ids.is_empty() {
+        "no roles".to_string()
+    } else {
+        protected_role_ids
+            .iter()
+            .map(|role_id| role_id.mention().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    Response::success(format!(
+        "Members with the {} role can't rename members with {}.",
+        actor_role.id.mention(),
+        protected_list
+    ))
+    .send(ctx)
+    .await?;
+
+    Ok(())
+}
+
+/// Show or change the nickname validation pipeline
+#[poise::command(slash_command, category = "Admin")]
+async fn validation(
+    ctx: Context<'_>,
+    #[description = "Step to change"] step: Option<Step>,
+    #[description = "What the step does with a nickname that fails it"] mode: Option<Mode>,
+    #[description = "Where to move the step, starting from 1"]
+    #[min = 1]
+    #[max = 7]
+    position: Option<u8>,
+    #[description = "Word to add to the blocklist"] add_blocked_word: Option<String>,
+    #[description = "Word to remove from the blocklist"] remove_blocked_word: Option<String>,
+    #[description = "Regex rule to add"] add_regex: Option<String>,
+    #[description = "Regex rule to remove"] remove_regex: Option<String>,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    let mut pipeline = load_pipeline(storage, &guild_id)?;
+
+    if let Some(step) = step {
+        if let Some(mode) = mode {
+            pipeline.set_mode(step, mode);
+        }
+        if let Some(position) = position {
+            pipeline.move_step(step, usize::from(position) - 1);
+        }
+    } else if mode.is_some() || position.is_some() {
+        Response::error("Specify `step` to change its mode or position.")
+            .send(ctx)
+            .await?;
+        return Ok(());
+    }
+
+    if let Some(word) = add_blocked_word {
+        let word = word.trim().to_lowercase();
+        if !word.is_empty() && !pipeline.blocked_words.contains(&word) {
+            pipeline.blocked_words.push(word);
+        }
+    }
+    if let Some(word) = remove_blocked_word {
+        let word = word.trim().to_lowercase();
+        pipeline.blocked_words.retain(|w| *w != word);
+    }
+    if let Some(rule) = add_regex {
+        if validation::case_insensitive(&rule).is_none() {
+            Response::error(format!("`{}` is not a valid regex.", rule))
+                .send(ctx)
+                .await?;
+            return Ok(());
+        }
+        if !pipeline.regex_rules.contains(&rule) {
+            pipeline.regex_rules.push(rule);
+        }
+    }
+    if let Some(rule) = remove_regex {
+        pipeline.regex_rules.retain(|r| *r != rule);
+    }
+
+    storage.set_setting(
+        ValidationPipeline,
+        &guild_id,
+        &serde_json::to_string(&pipeline)?,
+    )?;
+
+    Response::success(pipeline.describe()).send(ctx).await?;
+
+    Ok(())
+}
+
+/// Run a nickname through the validation pipeline without renaming anyone
+#[poise::command(slash_command, category = "Admin")]
+async fn test_nickname(
+    ctx: Context<'_>,
+    #[description = "Nickname to test"] nickname: String,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    let report = validate_nickname(storage, &guild_id, &nickname)?;
+
+    let mut lines: Vec<String> = report
+        .results
+        .iter()
+        .map(|(step, outcome)| {
+            let result = match outcome {
+                Outcome::Off => "off".to_string(),
+                Outcome::Passed => "passed".to_string(),
+                Outcome::Warned(problem) => format!("warned: {}", problem),
+                Outcome::Stripped(problem) => format!("stripped: {}", problem),
+                Outcome::Rejected(problem) => format!("rejected: {}", problem),
+            };
+            format!("{}: {}", step.label(), result)
+        })
+        .collect();
+    lines.push(match report.rejection() {
+        Some(reason) => format!("{} would be rejected: {}.", nickname, reason),
+        None => format!("{} would be applied as {}.", nickname, report.nickname),
+    });
+
+    Response::info(lines.join("\n")).send(ctx).await?;
+
+    Ok(())
+}
+
+/// Show how often each command is used and how often it fails
+#[poise::command(prefix_command, owners_only, category = "Owner")]
+pub(crate) async fn usage(ctx: Context<'_>) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let mut stats = storage.usage.report()?;
+    stats.sort_by(|a, b| b.1.cmp(&a.1));
+
+    let msg = if stats.is_empty() {
+        "No commands have been used yet.".to_string()
+    } else {
+        stats
+            .iter()
+            .map(|(command, invocations, errors)| {
+                format!(
+                    "{}: {} uses, {} errors ({:.1}%)",
+                    command,
+                    invocations,
+                    errors,
+                    *errors as f64 * 100.0 / (*invocations).max(1) as f64
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    ctx.say(msg).await?;
+
+    Ok(())
+}
+
+/// Show the storage schema version and the migrations applied to reach it
+#[poise::command(prefix_command, owners_only, category = "Owner")]
+pub(crate) async fn migrations(ctx: Context<'_>) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let version = storage.migrations.version()?;
+    let applied = storage.migrations.applied()?;
+
+    let mut lines = vec![format!(
+        "Schema version {} of {}.",
+        version,
+        migrations::latest_version()
+    )];
+    if applied.is_empty() {
+        lines.push("Nothing was migrated; the databases were created at this version.".into());
+    }
+    for (version, applied_at) in applied {
+        lines.push(format!(
+            "v{} <t:{}:f>: {}",
+            version,
+            applied_at,
+            migrations::describe(version)
+        ));
+    }
+    ctx.say(lines.join("\n")).await?;
+
+    Ok(())
+}
+
+enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+fn checklist_item(status: CheckStatus, text: String) -> String {
+    let symbol = match status {
+        CheckStatus::Pass => "✅",
+        CheckStatus::Warn => "⚠️",
+        CheckStatus::Fail => "❌",
+    };
+    format!("{} {}", symbol, text)
+}
+
+/// Server features that can get in the way of renames, and what to expect from each
+const CONFLICTING_FEATURES: &[(&str, &str)] = &[
+    (
+        "MEMBER_VERIFICATION_GATE_ENABLED",
+        "Membership screening is on; renaming members who haven't accepted the rules yet may fail.",
+    ),
+    (
+        "GUILD_ONBOARDING",
+        "Onboarding is on; renaming members who haven't finished it yet may fail.",
+    ),
+];
+
+/// Check that the app is set up correctly in this server
+#[poise::command(slash_command, category = "Admin")]
+async fn doctor(ctx: Context<'_>) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild = ctx.guild().ok_or::<Error>("Server is not cached".into())?;
+    let bot_id = ctx.serenity_context().cache.current_user_id();
+    let bot_member = guild.member(ctx.serenity_context(), bot_id).await?;
+    let bot_position = highest_role_position(&guild, &bot_member);
+    let mut checklist = Vec::new();
+
+    let missing_intents = ctx.data().missing_intents;
+    checklist.push(if missing_intents.contains(GatewayIntents::GUILD_MEMBERS) {
+        checklist_item(
+            CheckStatus::Fail,
+            "The Server Members intent is disabled, so nickname changes made outside the app aren't recorded in history or sent to watchers, and sweep, import_current, cleanup_roles, list_managed, allow_role and consent_campaign start without a channel don't work. Fix: enable it under Bot > Privileged Gateway Intents in the developer portal and restart the app.".into(),
+        )
+    } else {
+        checklist_item(
+            CheckStatus::Pass,
+            "The Server Members intent is enabled.".into(),
+        )
+    });
+    checklist.push(
+        if missing_intents.contains(GatewayIntents::GUILD_PRESENCES) {
+            checklist_item(
+                CheckStatus::Warn,
+                "The Presence intent is disabled. Nothing depends on it yet.".into(),
+            )
+        } else {
+            checklist_item(CheckStatus::Pass, "The Presence intent is enabled.".into())
+        },
+    );
+
+    let permissions = guild_permissions(&guild, &bot_member);
+    for (permission, name, needed_for) in [
+        (
+            Permissions::MANAGE_NICKNAMES,
+            "Manage Nicknames",
+            "change nicknames",
+        ),
+        (
+            Permissions::MANAGE_ROLES,
+            "Manage Roles",
+            "create and clean up its roles",
+        ),
+    ] {
+        checklist.push(if permissions.contains(permission) {
+            checklist_item(CheckStatus::Pass, format!("The app has the {} permission.", name))
+        } else {
+            checklist_item(
+                CheckStatus::Fail,
+                format!(
+                    "The app is missing the {} permission it needs to {}. Fix: grant it to the app's role in Server Settings > Roles.",
+                    name, needed_for
+                ),
+            )
+        });
+    }
+
+    for app_role in [Renamer, Allow] {
+        let role = match storage.roles.get(app_role, &guild.id)? {
+            Some(role_id) => match guild.roles.get(&role_id) {
+                Some(role) => role,
+                None => {
+                    checklist.push(checklist_item(
+                        CheckStatus::Fail,
+                        format!(
+                            "The {} role no longer exists. Fix: run /renamer admin set_roles.",
+                            app_role
+                        ),
+                    ));
+                    continue;
+                }
+            },
+            None => {
+                checklist.push(checklist_item(
+                    CheckStatus::Fail,
+                    format!(
+                        "No {} role is configured. Fix: run /renamer admin set_roles.",
+                        app_role
+                    ),
+                ));
+                continue;
+            }
+        };
+        checklist.push(checklist_item(
+            CheckStatus::Pass,
+            format!("The {} role is {}.", app_role, role.id.mention()),
+        ));
+
+        let created = storage.created_roles.list(&guild.id)?.contains(&role.id);
+        if created && role.position > bot_position {
+            checklist.push(checklist_item(
+                CheckStatus::Fail,
+                format!(
+                    "The {} role is above the app's highest role, so the app can't manage it. Fix: drag the app's role above it in Server Settings > Roles.",
+                    role.id.mention()
+                ),
+            ));
+        }
+    }
+
+    let roles_above = guild
+        .roles
+        .values()
+        .filter(|role| role.position > bot_position && !role.managed)
+        .count();
+    checklist.push(if roles_above == 0 {
+        checklist_item(
+            CheckStatus::Pass,
+            "The app's role is above every other role, so it can rename everyone but the owner."
+                .into(),
+        )
+    } else {
+        checklist_item(
+            CheckStatus::Fail,
+            format!(
+                "{} roles are above the app's highest role; members with them can't be renamed. Fix: drag the app's role higher in Server Settings > Roles.",
+                roles_above
+            ),
+        )
+    });
+
+    for (feature, warning) in CONFLICTING_FEATURES {
+        if guild.features.iter().any(|f| f == feature) {
+            checklist.push(checklist_item(CheckStatus::Warn, warning.to_string()));
+        }
+    }
+    checklist.push(checklist_item(
+        CheckStatus::Warn,
+        "Discord never lets apps rename the server owner.".into(),
+    ));
+    if storage.settings.get_flag(ObserveMode, &guild.id)? {
+        checklist.push(checklist_item(
+            CheckStatus::Warn,
+            "Observe mode is on, so renames are checked but not applied. Fix: run /renamer admin set_observe_mode when ready to enforce.".into(),
+        ));
+    }
+
+    Response::info(checklist.join("\n")).send(ctx).await?;
+
+    Ok(())
+}
+
+/// Turn off a command in this server
+#[poise::command(slash_command, category = "Admin")]
+async fn disable_command(
+    ctx: Context<'_>,
+    #[description = "Full name of the command, e.g. renamer random"] name: String,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    let name = name.trim().trim_start_matches('/').to_string();
+
+    let response = if !runnable_commands(&ctx.framework().options().commands).contains(&name) {
+        Response::error(format!("There is no command named {}.", name))
+    } else if name.starts_with("renamer admin") {
+        // Otherwise admins could lock themselves out of turning it back on
+        Response::error("Admin commands can't be disabled.")
+    } else if storage.disable_command(&guild_id, &name)? {
+        Response::info(format!("/{} was already disabled.", name))
+    } else {
+        Response::success(format!("/{} is now disabled in this server.", name))
+    };
+    response.send(ctx).await?;
+
+    Ok(())
+}
+
+/// Turn a disabled command back on in this server
+#[poise::command(slash_command, category = "Admin")]
+async fn enable_command(
+    ctx: Context<'_>,
+    #[description = "Full name of the command, e.g. renamer random"] name: Option<String>,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+
+    let response = match name {
+        Some(name) => {
+            let name = name.trim().trim_start_matches('/').to_string();
+            if storage.enable_command(&guild_id, &name)? {
+                Response::success(format!("/{} is enabled again.", name))
+            } else {
+                Response::info(format!("/{} was not disabled.", name))
+            }
+        }
+        None => {
+            let disabled = storage.disabled_commands.list(&guild_id)?;
+            if disabled.is_empty() {
+                Response::info("No commands are disabled in this server.")
+            } else {
+                Response::info(format!(
+                    "Disabled commands: {}",
+                    disabled
+                        .iter()
+                        .map(|name| format!("/{}", name))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            }
+        }
+    };
+    response.send(ctx).await?;
+
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    required_bot_permissions = "MANAGE_ROLES",
+    subcommands("allow_role_grant", "allow_role_revoke")
+)]
+async fn allow_role(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Give the Allow role to every member of another role
+#[poise::command(slash_command, rename = "grant", category = "Admin")]
+async fn allow_role_grant(
+    ctx: Context<'_>,
+    #[description = "Role whose members should get the Allow role"] role: Role,
+) -> Result<(), Error> {
+    bulk_allow(ctx, role, true).await
+}
+
+/// Take the Allow role from every member of another role
+#[poise::command(slash_command, rename = "revoke", category = "Admin")]
+async fn allow_role_revoke(
+    ctx: Context<'_>,
+    #[description = "Role whose members should lose the Allow role"] role: Role,
+) -> Result<(), Error> {
+    bulk_allow(ctx, role, false).await
+}
+
+async fn bulk_allow(ctx: Context<'_>, role: Role, grant: bool) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let http = ctx.http();
+
+    if !check_members_intent(&ctx).await? {
+        return Ok(());
+    }
+    if let Some(allow_role_id) = check_set_up(&ctx, Allow).await? {
+        let prompt = format!(
+            "{} the Allow role {} every member with the {} role?",
+            if grant { "Give" } else { "Take" },
+            if grant { "to" } else { "from" },
+            role.id.mention()
+        );
+        if !confirm(&ctx, &prompt).await? {
+            return Ok(());
+        }
+
+        let action = if grant {
+            "Granting the Allow role"
+        } else {
+            "Revoking the Allow role"
+        };
+        let mut progress = Progress::start(ctx, action).await?;
+        let mut members: Vec<Member> = guild_members(&ctx)
+            .await?
+            .into_iter()
+            .filter(|member| member.roles.contains(&role.id))
+            .collect();
+
+        let (mut changed, mut skipped) = (0, 0);
+        let total = members.len();
+        let route = Route::GuildsIdMembersIdRolesId(role.guild_id.0);
+        for (i, member) in members.iter_mut().enumerate() {
+            progress.update(i, total).await;
+
+            // Wait out a used-up rate limit here rather than having requests rejected
+            if let Some(wait) = rate_limit_wait(http, route).await {
+                tokio::time::sleep(wait).await;
+            }
+
+            let has_allow_role = member.roles.contains(&allow_role_id);
+            if grant {
+                // Don't override members who opted out everywhere
+                if has_allow_role || member.user.bot || storage.optouts.contains(&member.user.id)? {
+                    skipped += 1;
+                    continue;
+                }
+                member.add_role(http, allow_role_id).await?;
+            } else {
+                if !has_allow_role {
+                    skipped += 1;
+                    continue;
+                }
+                member.remove_role(http, allow_role_id).await?;
+            }
+            changed += 1;
+        }
+
+        progress
+            .finish(&format!(
+                "{} the Allow role {} {} member(s); skipped {}.",
+                if grant { "Granted" } else { "Revoked" },
+                if grant { "to" } else { "from" },
+                changed,
+                skipped
+            ))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Require a second renamer's approval to rename members of a role
+#[poise::command(slash_command, category = "Admin")]
+async fn set_staff_role(
+    ctx: Context<'_>,
+    #[description = "Role whose members count as staff"] role: Role,
+    #[description = "Whether renaming members of the role needs a second renamer's approval"]
+    staff: bool,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+
+    let mut role_ids = staff_roles(storage, &guild_id)?;
+    role_ids.retain(|role_id| *role_id != role.id);
+    if staff {
+        role_ids.push(role.id);
+    }
+    storage.set_setting(
+        StaffRoles,
+        &guild_id,
+        &role_ids
+            .iter()
+            .map(|role_id| role_id.0.to_string())
+            .collect::<Vec<_>>()
+            .join(","),
+    )?;
+
+    let msg = if staff {
+        format!(
+            "Renaming members with the {} role now needs a second renamer's approval.",
+            role.id.mention()
+        )
+    } else {
+        format!(
+            "Renaming members with the {} role no longer needs a second approval.",
+            role.id.mention()
+        )
+    };
+    Response::success(msg).send(ctx).await?;
+
+    Ok(())
+}
+
+/// Let server boosters restyle their own nicknames without the Renamer role
+#[poise::command(slash_command, category = "Admin")]
+async fn set_booster_perk(
+    ctx: Context<'_>,
+    #[description = "What boosters may do to their own nickname"] perk: BoosterPerk,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+
+    storage.set_setting(BoosterPerkTier, &guild_id, perk.label())?;
+
+    let msg = match perk {
+        BoosterPerk::Off => "Boosters now need the Renamer role like everyone else.",
+        BoosterPerk::SelfRename => "Boosters can now /rename themselves.",
+        BoosterPerk::Styling => {
+            "Boosters can now /rename, transliterate and randomize their own nicknames."
+        }
+    };
+    Response::success(msg).send(ctx).await?;
+
+    Ok(())
+}
+
+/// Snapshots shown by /renamer admin config_history
+const CONFIG_HISTORY_LIMIT: usize = 10;
+
+/// Show earlier versions of this server's configuration
+#[poise::command(slash_command, category = "Admin")]
+async fn config_history(ctx: Context<'_>) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    let snapshots = storage.config_history.list(&guild_id)?;
+
+    if snapshots.is_empty() {
+        Response::info("This server's configuration hasn't changed yet.")
+            .send(ctx)
+            .await?;
+        return Ok(());
+    }
+
+    let lines: Vec<String> = snapshots
+        .iter()
+        .rev()
+        .take(CONFIG_HISTORY_LIMIT)
+        .map(|snapshot| {
+            format!(
+                "`{}` <t:{}:f> before changing {} ({} setting(s), {} target rule(s), {} disabled command(s), Renamer role {}, Allow role {})",
+                snapshot.id,
+                snapshot.timestamp,
+                snapshot.change,
+                snapshot.settings.len(),
+                snapshot.target_rules.len(),
+                snapshot.disabled_commands.len(),
+                describe_snapshot_role(snapshot.renamer_role_id, &snapshot.renamer_role),
+                describe_snapshot_role(snapshot.allow_role_id, &snapshot.allow_role)
+            )
+        })
+        .collect();
+    Response::info(format!(
+        "{}\nRestore one with /renamer admin config_rollback.",
+        lines.join("\n")
+    ))
+    .send(ctx)
+    .await?;
+
+    Ok(())
+}
+
+/// A snapshot's role as a mention, or as its name in snapshots from before roles were
+/// stored by ID
+fn describe_snapshot_role(role_id: Option<u64>, name: &Option<String>) -> String {
+    match (role_id, name) {
+        (Some(role_id), _) => RoleId(role_id).mention().to_string(),
+        (None, Some(name)) => name.clone(),
+        (None, None) => "unset".to_string(),
+    }
+}
+
+/// Restore an earlier version of this server's configuration
+#[poise::command(slash_command, category = "Admin")]
+async fn config_rollback(
+    ctx: Context<'_>,
+    #[description = "ID from /renamer admin config_history"] id: u64,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+
+    let Some(snapshot) = storage.config_history.get(&guild_id, id)? else {
+        Response::error(format!("There is no configuration with ID {}.", id))
+            .send(ctx)
+            .await?;
+        return Ok(());
+    };
+
+    // Keep the current configuration so the rollback can itself be rolled back
+    storage.record_config(&guild_id, "rollback")?;
+    storage.settings.restore(&guild_id, &snapshot.settings)?;
+    for (app_role, role_id, name) in [
+        (Renamer, snapshot.renamer_role_id, &snapshot.renamer_role),
+        (Allow, snapshot.allow_role_id, &snapshot.allow_role),
+    ] {
+        // Snapshots from before roles were stored by ID only have the name
+        let role_id = match (role_id, name) {
+            (Some(role_id), _) => Some(RoleId(role_id)),
+            (None, Some(name)) => role_by_name!(guild_id, ctx.http(), name).map(|role| role.id),
+            (None, None) => None,
+        };
+        storage.roles.restore(app_role, &guild_id, role_id)?;
+    }
+    storage
+        .target_rules
+        .restore(&guild_id, &snapshot.target_rules)?;
+    storage
+        .disabled_commands
+        .restore(&guild_id, &snapshot.disabled_commands)?;
+    storage.config_changed(&guild_id, "rollback");
+
+    Response::success(format!(
+        "Restored the configuration from <t:{}:f>, before {} changed.",
+        snapshot.timestamp, snapshot.change
+    ))
+    .send(ctx)
+    .await?;
+
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    subcommands("preset_list", "preset_apply", "preset_export")
+)]
+async fn preset(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// List the presets built into renamer
+#[poise::command(slash_command, rename = "list", category = "Admin")]
+async fn preset_list(ctx: Context<'_>) -> Result<(), Error> {
+    let lines: Vec<String> = preset::built_in()
+        .iter()
+        .map(|preset| preset.describe())
+        .collect();
+    Response::info(format!(
+        "{}\nApply one by name, or import a file from /renamer admin preset export.",
+        lines.join("\n")
+    ))
+    .send(ctx)
+    .await?;
+
+    Ok(())
+}
+
+/// Add a preset's blocked words, regex rules and seed words to this server's
+#[poise::command(slash_command, rename = "apply", category = "Admin")]
+async fn preset_apply(
+    ctx: Context<'_>,
+    #[description = "Name of a built-in preset"] name: Option<String>,
+    #[description = "Preset file exported from another server"] file: Option<serenity::Attachment>,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+
+    let preset: Preset = match (name, file) {
+        (Some(name), None) => match preset::find_built_in(&name) {
+            Some(preset) => preset,
+            None => {
+                Response::error(format!("There is no built-in preset called {}.", name))
+                    .send(ctx)
+                    .await?;
+                return Ok(());
+            }
+        },
+        (None, Some(file)) => match serde_json::from_slice(&file.download().await?) {
+            Ok(preset) => preset,
+            Err(_) => {
+                Response::error(format!("{} is not a renamer preset.", file.filename))
+                    .send(ctx)
+                    .await?;
+                return Ok(());
+            }
+        },
+        _ => {
+            Response::error("Specify either `name` or `file`.")
+                .send(ctx)
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let invalid_rules: Vec<&String> = preset
+        .regex_rules
+        .iter()
+        .filter(|rule| validation::case_insensitive(rule).is_none())
+        .collect();
+    if !invalid_rules.is_empty() {
+        let list: Vec<String> = invalid_rules.iter().map(|r| format!("`{}`", r)).collect();
+        Response::error(format!(
+            "The preset has invalid regex rules: {}",
+            list.join(", ")
+        ))
+        .send(ctx)
+        .await?;
+        return Ok(());
+    }
+    if preset.is_empty() {
+        Response::error(format!("The {} preset is empty.", preset.name))
+            .send(ctx)
+            .await?;
+        return Ok(());
+    }
+
+    let mut pipeline = load_pipeline(storage, &guild_id)?;
+    preset.apply(&mut pipeline);
+    storage.set_setting(
+        ValidationPipeline,
+        &guild_id,
+        &serde_json::to_string(&pipeline)?,
+    )?;
+
+    if !preset.seed_words.is_empty() {
+        let mut seed_words: Vec<String> = storage
+            .settings
+            .get(RandomSeedWords, &guild_id)?
+            .map(|words| words.split(',').map(|w| w.trim().to_string()).collect())
+            .unwrap_or_default();
+        for word in &preset.seed_words {
+            if !seed_words.contains(word) {
+                seed_words.push(word.clone());
+            }
+        }
+        storage.set_setting(RandomSeedWords, &guild_id, &seed_words.join(","))?;
+    }
+
+    Response::success(format!(
+        "Applied {}. Turn on the blocklist and regex steps with /renamer admin validation \
+        if they're off.\n{}",
+        preset.describe(),
+        pipeline.describe()
+    ))
+    .send(ctx)
+    .await?;
+
+    Ok(())
+}
+
+/// Export this server's blocked words, regex rules and seed words as a preset file
+#[poise::command(slash_command, rename = "export", category = "Admin")]
+async fn preset_export(
+    ctx: Context<'_>,
+    #[description = "Name to give the preset"] name: Option<String>,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    let pipeline = load_pipeline(storage, &guild_id)?;
+
+    let preset = Preset {
+        name: name
+            .or_else(|| guild_id.name(ctx.serenity_context()))
+            .unwrap_or_else(|| "preset".into()),
+        blocked_words: pipeline.blocked_words,
+        regex_rules: pipeline.regex_rules,
+        seed_words: storage
+            .settings
+            .get(RandomSeedWords, &guild_id)?
+            .map(|words| words.split(',').map(|w| w.trim().to_string()).collect())
+            .unwrap_or_default(),
+    };
+    let data = serde_json::to_vec_pretty(&preset)?;
+
+    ctx.send(|m| {
+        m.ephemeral(true)
+            .content(preset.describe())
+            .attachment(AttachmentType::Bytes {
+                data: Cow::Owned(data),
+                filename: "renamer_preset.json".into(),
+            })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// How long to wait between consent campaign DMs, so they don't look like spam to Discord
+const CONSENT_DM_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Campaigns shown by /renamer admin consent_campaign report
+const CONSENT_REPORT_LIMIT: usize = 5;
+
+#[poise::command(
+    slash_command,
+    required_bot_permissions = "MANAGE_ROLES",
+    subcommands("consent_campaign_start", "consent_campaign_report")
+)]
+async fn consent_campaign(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Invite members without the Allow role to opt in to nickname changes
+#[poise::command(slash_command, rename = "start", category = "Admin")]
+async fn consent_campaign_start(
+    ctx: Context<'_>,
+    #[description = "Post one invitation in this channel instead of DMing each member"]
+    channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    let http = ctx.http();
+
+    let Some(allow_role_id) = check_set_up(&ctx, Allow).await? else {
+        return Ok(());
+    };
+    // Only DMing each member needs the member list
+    if channel.is_none() && !check_members_intent(&ctx).await? {
+        return Ok(());
+    }
+    let prompt = match &channel {
+        Some(channel) => format!(
+            "Post an invitation to opt in to nickname changes in {}?",
+            channel.mention()
+        ),
+        None => {
+            "DM every member without the Allow role an invitation to opt in to nickname changes?"
+                .to_string()
+        }
+    };
+    if !confirm(&ctx, &prompt).await? {
+        return Ok(());
+    }
+
+    let id = storage.consent_campaigns.insert(ConsentCampaign {
+        id: 0,
+        guild_id: guild_id.0,
+        started_at: unix_now(),
+        contacted: 0,
+        opted_in: 0,
+    })?;
+    let server_name = guild_id
+        .name(ctx.serenity_context())
+        .unwrap_or_else(|| "this server".into());
+    let invitation = format!(
+        "{} uses renamer, which lets members with the Renamer role change the nicknames \
+        of members who allow it. Press the button to allow it; you can stop any time with \
+        /renamer disallow.",
+        server_name
+    );
+
+    if let Some(channel) = channel {
+        channel
+            .send_message(http, |m| consent_invitation(m, &invitation, id))
+            .await?;
+        Response::success(format!(
+            "Posted the invitation in {}. See how many opted in with /renamer admin consent_campaign report.",
+            channel.mention()
+        ))
+        .send(ctx)
+        .await?;
+        return Ok(());
+    }
+
+    let progress = Progress::start(ctx, "Finding members to invite").await?;
+    let members: Vec<Member> = guild_members(&ctx)
+        .await?
+        .into_iter()
+        .filter(|member| !member.user.bot && !member.roles.contains(&allow_role_id))
+        .collect();
+    progress
+        .finish(&format!(
+            "Inviting {} member(s) by DM, one a second. You'll get a DM when it's done; see how many opted in with /renamer admin consent_campaign report.",
+            members.len()
+        ))
+        .await?;
+
+    // DMing a large server takes longer than the interaction stays usable
+    tokio::spawn(invite_members(
+        ctx.serenity_context().http.clone(),
+        ctx.data().storage.clone(),
+        id,
+        members,
+        invitation,
+        ctx.author().clone(),
+    ));
+
+    Ok(())
+}
+
+fn consent_invitation<'a, 'b>(
+    m: &'b mut serenity::CreateMessage<'a>,
+    invitation: &str,
+    campaign_id: u64,
+) -> &'b mut serenity::CreateMessage<'a> {
+    m.content(invitation).components(|c| {
+        c.create_action_row(|r| {
+            r.create_button(|b| {
+                b.custom_id(ComponentAction::ConsentOptIn(campaign_id).custom_id())
+                    .label("Allow nickname changes")
+                    .style(ButtonStyle::Success)
+            })
+        })
+    })
+}
+
+/// DMs each member a consent campaign's invitation, counting them in the campaign as it
+/// goes, then tells the admin who started it how it went
+async fn invite_members(
+    http: Arc<Http>,
+    storage: Arc<Storage>,
+    campaign_id: u64,
+    members: Vec<Member>,
+    invitation: String,
+    admin: User,
+) {
+    let (mut contacted, mut unreachable) = (0, 0);
+    for member in members {
+        // Members who opted out everywhere have already answered
+        match storage.optouts.contains(&member.user.id) {
+            Ok(false) => {}
+            Ok(true) => continue,
+            Err(e) => {
+                error!("Failed to read opt-outs: {}", e);
+                continue;
+            }
+        }
+        let result = member
+            .user
+            .direct_message(&http, |m| consent_invitation(m, &invitation, campaign_id))
+            .await;
+        match result {
+            Ok(_) => {
+                contacted += 1;
+                if let Err(e) = storage.consent_campaigns.record_contacted(campaign_id) {
+                    error!("Failed to update consent campaign {}: {}", campaign_id, e);
+                }
+            }
+            Err(_) => unreachable += 1,
+        }
+        tokio::time::sleep(CONSENT_DM_INTERVAL).await;
+    }
+
+    let result = admin
+        .direct_message(&http, |m| {
+            m.content(format!(
+                "Consent campaign finished: invited {} member(s); {} don't accept DMs from the app.",
+                contacted, unreachable
+            ))
+        })
+        .await;
+    if let Err(e) = result {
+        warn!(
+            "Failed to tell {} the consent campaign finished: {}",
+            admin.id, e
+        );
+    }
+}
+
+/// Show how many members opted in through each consent campaign
+#[poise::command(slash_command, rename = "report", category = "Admin")]
+async fn consent_campaign_report(ctx: Context<'_>) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    let campaigns = storage.consent_campaigns.for_guild(&guild_id)?;
+
+    if campaigns.is_empty() {
+        Response::info("No consent campaigns have been run in this server.")
+            .send(ctx)
+            .await?;
+        return Ok(());
+    }
+
+    let lines: Vec<String> = campaigns
+        .iter()
+        .rev()
+        .take(CONSENT_REPORT_LIMIT)
+        .map(|campaign| {
+            if campaign.contacted == 0 {
+                format!(
+                    "<t:{}:f> posted in a channel: {} opted in",
+                    campaign.started_at, campaign.opted_in
+                )
+            } else {
+                format!(
+                    "<t:{}:f> {} DMed: {} opted in ({:.0}%)",
+                    campaign.started_at,
+                    campaign.contacted,
+                    campaign.opted_in,
+                    campaign.opted_in as f64 * 100.0 / campaign.contacted as f64
+                )
+            }
+        })
+        .collect();
+    Response::info(lines.join("\n")).send(ctx).await?;
+
+    Ok(())
+}
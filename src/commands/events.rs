@@ -0,0 +1,319 @@
+//! Framework hooks, gateway events and background tasks
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use poise::serenity_prelude as serenity;
+use poise::serenity_prelude::{GuildId, Member, Mentionable, UserId};
+use tokio::sync::broadcast::Receiver;
+use tracing::{error, info, warn};
+
+use super::history::view_history;
+use super::optin::consent_opt_in;
+use super::rename::{
+    ask_rename_again, rename_again, review_nickname_request, review_staff_approval, undo_rename,
+};
+use super::sweep::fix_nickname;
+use super::{identify_member, nickname_applied, ComponentAction, Context, Data, Error};
+use crate::response::Response;
+use crate::services::events::{next_event, Event};
+use crate::services::storage::AppRole::*;
+use crate::services::storage::{unix_now, ApprovalKind, HistoryEntry, HistoryKind, Storage};
+
+/// Updates the messages of staff renames and nickname requests that expired without a
+/// review, which would otherwise keep asking for approval even after a restart
+pub(crate) async fn expire_staff_approvals(ctx: serenity::Context, storage: Arc<Storage>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(10 * 60));
+    loop {
+        interval.tick().await;
+        let expired = match storage.staff_approvals.take_expired(unix_now()) {
+            Ok(expired) => expired,
+            Err(e) => {
+                error!("Failed to expire staff rename approvals: {}", e);
+                continue;
+            }
+        };
+        for approval in expired {
+            let Some((channel_id, message_id)) = approval.message else {
+                continue;
+            };
+            let content = match approval.kind {
+                ApprovalKind::StaffRename => format!(
+                    "The request by {} to set the nickname of {} to {} expired before it was approved.",
+                    UserId(approval.actor_id).mention(),
+                    UserId(approval.target_id).mention(),
+                    approval.nickname
+                ),
+                ApprovalKind::NicknameRequest => format!(
+                    "The request by {} for the nickname {} expired before it was reviewed.",
+                    UserId(approval.actor_id).mention(),
+                    approval.nickname
+                ),
+            };
+            let result = serenity::ChannelId(channel_id)
+                .edit_message(&ctx.http, message_id, |m| {
+                    m.content(content).components(|c| c)
+                })
+                .await;
+            if let Err(e) = result {
+                error!("Failed to update expired staff rename approval: {}", e);
+            }
+        }
+    }
+}
+
+pub(crate) async fn record_invocation(ctx: Context<'_>) {
+    let storage = &ctx.data().storage;
+    if let Err(e) = storage
+        .usage
+        .record_invocation(&ctx.command().qualified_name)
+    {
+        error!("Failed to record command usage: {}", e);
+    }
+}
+
+/// Stops commands an admin has disabled in the guild from running
+pub(crate) async fn command_enabled(ctx: Context<'_>) -> Result<bool, Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = match ctx.guild_id() {
+        Some(guild_id) => guild_id,
+        None => return Ok(true),
+    };
+    if !storage
+        .disabled_commands
+        .contains(&guild_id, &ctx.command().qualified_name)?
+    {
+        return Ok(true);
+    }
+    Response::error("An admin has disabled this command in this server.")
+        .send(ctx)
+        .await?;
+    Ok(false)
+}
+
+pub(crate) async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
+    if let poise::FrameworkError::Command { ctx, .. } = &error {
+        if let Err(e) = ctx
+            .data()
+            .storage
+            .usage
+            .record_error(&ctx.command().qualified_name)
+        {
+            error!("Failed to record command error: {}", e);
+        }
+    }
+    if let Err(e) = poise::builtins::on_error(error).await {
+        error!("Failed to handle error: {}", e);
+    }
+}
+
+pub(crate) async fn event_handler(
+    ctx: &serenity::Context,
+    event: &poise::Event<'_>,
+    _framework: poise::FrameworkContext<'_, Data, Error>,
+    data: &Data,
+) -> Result<(), Error> {
+    let storage = &data.storage;
+    match event {
+        poise::Event::GuildCreate { guild, .. } => resolve_role_names(storage, guild)?,
+        poise::Event::GuildMemberUpdate {
+            old_if_available: Some(old),
+            new,
+        } if old.nick != new.nick => {
+            if renamed_through_app(storage, new)?.is_none() {
+                record_external_change(storage, old, new)?;
+            }
+        }
+        poise::Event::InteractionCreate {
+            interaction: serenity::Interaction::MessageComponent(mci),
+        } => match ComponentAction::parse(&mci.data.custom_id) {
+            Some(ComponentAction::ReviewStaffApproval { id, approve }) => {
+                review_staff_approval(storage, ctx, mci, id, approve).await?
+            }
+            Some(ComponentAction::ReviewNicknameRequest { id, approve }) => {
+                review_nickname_request(storage, ctx, mci, id, approve).await?
+            }
+            Some(ComponentAction::RenameAgain(id)) => {
+                ask_rename_again(storage, ctx, mci, id).await?
+            }
+            Some(ComponentAction::Undo(id)) => undo_rename(storage, ctx, mci, id).await?,
+            Some(ComponentAction::ViewHistory(id)) => view_history(storage, ctx, mci, id).await?,
+            Some(ComponentAction::ConsentOptIn(id)) => {
+                consent_opt_in(storage, ctx, mci, id).await?
+            }
+            Some(ComponentAction::FixNickname(user_id)) => {
+                fix_nickname(storage, ctx, mci, UserId(user_id)).await?
+            }
+            None => {}
+        },
+        poise::Event::InteractionCreate {
+            interaction: serenity::Interaction::ModalSubmit(msi),
+        } => {
+            if let Some(ComponentAction::RenameAgain(id)) =
+                ComponentAction::parse(&msi.data.custom_id)
+            {
+                rename_again(storage, ctx, msi, id).await?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Who renamed the member through this app, if their latest nickname change was made
+/// through it rather than directly in Discord. Those changes are recorded in history by
+/// the rename itself.
+fn renamed_through_app(storage: &Storage, member: &Member) -> Result<Option<UserId>, Error> {
+    // Discord can send the update while the rename is still waiting to be recorded, or
+    // after it was
+    if let Some((actor_id, nickname)) = storage
+        .pending_renames
+        .get(&member.guild_id, &member.user.id)?
+    {
+        if nickname_applied(member, &nickname) {
+            return Ok(Some(actor_id));
+        }
+    }
+    let history = storage
+        .history
+        .for_member(&member.guild_id, &member.user.id)?;
+    let renamed_by = match history.last() {
+        Some(HistoryEntry {
+            kind: HistoryKind::Rename,
+            actor_id: Some(actor_id),
+            nickname,
+            timestamp,
+            ..
+        }) if *nickname == member.nick && unix_now().saturating_sub(*timestamp) < 60 => {
+            Some(UserId(*actor_id))
+        }
+        _ => None,
+    };
+    Ok(renamed_by)
+}
+
+/// Records a nickname change made directly in Discord so history stays complete
+fn record_external_change(storage: &Storage, old: &Member, new: &Member) -> Result<(), Error> {
+    storage.record_rename(HistoryEntry {
+        id: 0,
+        guild_id: new.guild_id.0,
+        user_id: new.user.id.0,
+        actor_id: None,
+        previous_nickname: old.nick.clone(),
+        nickname: new.nick.clone(),
+        timestamp: unix_now(),
+        kind: HistoryKind::External,
+    })?;
+    Ok(())
+}
+
+/// Logs renames and configuration changes as they are stored
+pub(crate) async fn log_events(mut events: Receiver<Event>) {
+    while let Some(event) = next_event(&mut events, "Event log").await {
+        match event {
+            Event::Rename(entry) => info!(
+                "Nickname of {} in guild {} changed from {:?} to {:?} ({})",
+                entry.user_id,
+                entry.guild_id,
+                entry.previous_nickname,
+                entry.nickname,
+                match entry.actor_id {
+                    Some(actor_id) => format!("by {}", actor_id),
+                    None => "outside renamer".to_string(),
+                }
+            ),
+            Event::ConfigChanged { guild_id, change } => {
+                info!("{} changed in guild {}", change, guild_id)
+            }
+        }
+    }
+}
+
+/// DMs everyone watching a member whose nickname just changed, whether through this
+/// app or directly in Discord
+pub(crate) async fn notify_watchers(
+    ctx: serenity::Context,
+    storage: Arc<Storage>,
+    mut events: Receiver<Event>,
+) {
+    while let Some(event) = next_event(&mut events, "Watch notifications").await {
+        if let Event::Rename(entry) = event {
+            if let Err(e) = notify_watchers_of(&ctx, &storage, &entry).await {
+                error!("Failed to notify watchers: {}", e);
+            }
+        }
+    }
+}
+
+async fn notify_watchers_of(
+    ctx: &serenity::Context,
+    storage: &Storage,
+    entry: &HistoryEntry,
+) -> Result<(), Error> {
+    let guild_id = GuildId(entry.guild_id);
+    let user_id = UserId(entry.user_id);
+    let watchers = storage.watches.watchers(&guild_id, &user_id)?;
+    if watchers.is_empty() {
+        return Ok(());
+    }
+
+    let changed_by = match entry.actor_id {
+        Some(actor_id) => format!("by {} through renamer", UserId(actor_id).mention()),
+        None => "outside renamer".to_string(),
+    };
+    let member = match ctx.cache.member(guild_id, user_id) {
+        Some(member) => identify_member(&member),
+        None => user_id.mention().to_string(),
+    };
+    let guild_name = guild_id.name(ctx).unwrap_or_else(|| guild_id.to_string());
+    let content = format!(
+        "The nickname of {} in {} was changed {} from {} to {}.",
+        member,
+        guild_name,
+        changed_by,
+        entry.previous_nickname.as_deref().unwrap_or("(none)"),
+        entry.nickname.as_deref().unwrap_or("(none)")
+    );
+
+    for watcher_id in watchers {
+        let dm_result = match watcher_id.create_dm_channel(ctx).await {
+            Ok(channel) => channel.say(&ctx.http, &content).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = dm_result {
+            error!("Failed to notify watcher {}: {}", watcher_id, e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Looks up the roles that were stored by name before the migration to role IDs, once the
+/// server's roles are known
+fn resolve_role_names(storage: &Storage, guild: &serenity::Guild) -> Result<(), Error> {
+    for app_role in [Renamer, Allow] {
+        let Some(name) = storage.unresolved_role_names.take(app_role, &guild.id)? else {
+            continue;
+        };
+        // A role set since the migration takes precedence
+        if storage.roles.get(app_role, &guild.id)?.is_some() {
+            continue;
+        }
+        match guild.role_by_name(&name) {
+            Some(role) => {
+                storage.roles.restore(app_role, &guild.id, Some(role.id))?;
+                info!(
+                    "{} role in guild {} resolved from {} to {}",
+                    app_role, guild.id, name, role.id
+                );
+            }
+            None => warn!(
+                "{} role {} in guild {} no longer exists; it needs to be set again",
+                app_role, name, guild.id
+            ),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,212 @@
+//! /renamer help and working out which commands a member can run
+
+use poise::serenity_prelude::{Mentionable, Permissions};
+
+use super::{guild_permissions, Context, Data, Error, VERSION};
+use crate::response::Response;
+use crate::services::storage::AppRole::*;
+
+#[poise::command(slash_command, category = "Member")]
+pub(super) async fn help(
+    ctx: Context<'_>,
+    #[description = "Specific command to show help about"] command: Option<String>,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let commands = &ctx.framework().options().commands;
+    let prefix = ctx
+        .framework()
+        .options()
+        .prefix_options
+        .prefix
+        .as_deref()
+        .unwrap_or_default();
+    let runnable = runnable_commands_for(&ctx, commands).await?;
+
+    if let Some(name) = command {
+        let name = name.trim().trim_start_matches('/');
+        let name = name.strip_prefix(prefix).unwrap_or(name);
+        match runnable
+            .iter()
+            .find(|command| command.qualified_name == name || command.name == name)
+        {
+            Some(command) => {
+                let parameters = command
+                    .parameters
+                    .iter()
+                    .map(|parameter| {
+                        format!(
+                            "`{}`{}: {}",
+                            parameter.name,
+                            if parameter.required {
+                                ""
+                            } else {
+                                " (optional)"
+                            },
+                            parameter.description.as_deref().unwrap_or("")
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                ctx.send(|m| {
+                    m.ephemeral(true).embed(|e| {
+                        e.title(invocation(command, prefix))
+                            .description(command.description.as_deref().unwrap_or(""));
+                        if !parameters.is_empty() {
+                            e.field("Options", parameters, false);
+                        }
+                        e
+                    })
+                })
+                .await?;
+            }
+            None => {
+                Response::error(format!("There is no command named {} you can use.", name))
+                    .send(ctx)
+                    .await?;
+            }
+        }
+        return Ok(());
+    }
+
+    let renamer_role = match ctx.guild_id() {
+        Some(guild_id) => storage.roles.get(Renamer, &guild_id)?,
+        None => None,
+    };
+    let categories = [
+        ("Member", "Anyone can use these.".to_string()),
+        (
+            "Renamer",
+            match renamer_role {
+                Some(role_id) => format!("Requires the {} role.", role_id.mention()),
+                None => "Requires the Renamer role.".to_string(),
+            },
+        ),
+        ("Moderator", "Requires Manage Nicknames.".to_string()),
+        ("Admin", "Requires Administrator.".to_string()),
+        ("Owner", "Only for the app's owners.".to_string()),
+    ];
+
+    ctx.send(|m| {
+        m.ephemeral(true).embed(|e| {
+            e.title("renamer help").footer(|f| {
+                f.text(format!(
+                    "renamer version {} · /renamer help <command> for more on a command",
+                    VERSION
+                ))
+            });
+            for (category, requirement) in &categories {
+                let lines: Vec<String> = runnable
+                    .iter()
+                    .filter(|command| command.category.as_deref() == Some(*category))
+                    .map(|command| {
+                        format!(
+                            "`{}` {}",
+                            invocation(command, prefix),
+                            command.description.as_deref().unwrap_or("")
+                        )
+                    })
+                    .collect();
+                if lines.is_empty() {
+                    continue;
+                }
+                // Embed field values are limited to 1024 characters
+                let mut value = format!("*{}*", requirement);
+                for line in lines {
+                    if value.len() + line.len() + 1 > 1024 {
+                        e.field(*category, &value, false);
+                        value.clear();
+                    }
+                    if !value.is_empty() {
+                        value.push('\n');
+                    }
+                    value.push_str(&line);
+                }
+                e.field(*category, value, false);
+            }
+            e
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// How the command is run: as a slash command if it is one, otherwise with the configured
+/// prefix
+fn invocation(command: &poise::Command<Data, Error>, prefix: &str) -> String {
+    if command.slash_action.is_some() {
+        format!("/{}", command.qualified_name)
+    } else {
+        format!("{}{}", prefix, command.qualified_name)
+    }
+}
+
+/// Commands the invoking user could run here, skipping parent commands
+async fn runnable_commands_for<'a>(
+    ctx: &Context<'_>,
+    commands: &'a [poise::Command<Data, Error>],
+) -> Result<Vec<&'a poise::Command<Data, Error>>, Error> {
+    let storage = &ctx.data().storage;
+    let is_owner = ctx.framework().options().owners.contains(&ctx.author().id);
+    let (permissions, is_renamer) = match (ctx.guild(), ctx.author_member().await) {
+        (Some(guild), Some(member)) => {
+            let is_renamer = storage
+                .roles
+                .get(Renamer, &guild.id)?
+                .map_or(false, |role_id| member.roles.contains(&role_id));
+            (guild_permissions(&guild, &member), is_renamer)
+        }
+        _ => (Permissions::empty(), false),
+    };
+
+    let mut runnable = Vec::new();
+    let mut stack: Vec<(&poise::Command<Data, Error>, Permissions)> = commands
+        .iter()
+        .map(|command| (command, Permissions::empty()))
+        .collect();
+    while let Some((command, inherited_permissions)) = stack.pop() {
+        // Parents' required permissions apply to their subcommands too
+        let required_permissions = inherited_permissions | command.required_permissions;
+        if !permissions.contains(required_permissions) || command.owners_only && !is_owner {
+            continue;
+        }
+        if !command.subcommands.is_empty() {
+            stack.extend(
+                command
+                    .subcommands
+                    .iter()
+                    .map(|subcommand| (subcommand, required_permissions)),
+            );
+            continue;
+        }
+        if command.category.as_deref() == Some("Renamer") && !is_renamer {
+            continue;
+        }
+        if let Some(guild_id) = ctx.guild_id() {
+            if storage
+                .disabled_commands
+                .contains(&guild_id, &command.qualified_name)?
+            {
+                continue;
+            }
+        }
+        runnable.push(command);
+    }
+    runnable.sort_by(|a, b| a.qualified_name.cmp(&b.qualified_name));
+
+    Ok(runnable)
+}
+
+/// Qualified names of every command that can be run, skipping parent commands
+pub(super) fn runnable_commands(commands: &[poise::Command<Data, Error>]) -> Vec<String> {
+    commands
+        .iter()
+        .flat_map(|command| {
+            if command.subcommands.is_empty() {
+                vec![command.qualified_name.clone()]
+            } else {
+                runnable_commands(&command.subcommands)
+            }
+        })
+        .collect()
+}
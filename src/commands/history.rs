@@ -0,0 +1,423 @@
+//! Nickname history, member profiles and watches
+
+use std::borrow::Cow;
+use std::time::Duration;
+
+use poise::serenity_prelude as serenity;
+use poise::serenity_prelude::{
+    AttachmentType, ButtonStyle, CacheHttp, CollectComponentInteraction, InteractionResponseType,
+    Member, Mentionable, Permissions, UserId,
+};
+
+use super::{
+    describe_member, guild_permissions, identify_member, is_renamer, respond_privately, Context,
+    Error,
+};
+use crate::response::Response;
+use crate::services::storage::{HistoryEntry, HistoryKind, Storage};
+
+/// Shows the latest nickname history of the member renamed in a history entry, to
+/// whoever may see it
+pub(super) async fn view_history(
+    storage: &Storage,
+    ctx: &serenity::Context,
+    mci: &serenity::MessageComponentInteraction,
+    history_id: u64,
+) -> Result<(), Error> {
+    let guild_id = mci.guild_id.ok_or::<Error>("Not in a server".into())?;
+    let Some(entry) = storage.history.get(&guild_id, history_id)? else {
+        respond_privately(
+            ctx,
+            mci,
+            Response::error("That rename is no longer recorded."),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    // Same rule as /renamer history: your own, or anyone's with renamer or moderator rights
+    let allowed = match &mci.member {
+        Some(viewer) if viewer.user.id.0 == entry.user_id => true,
+        Some(viewer) => {
+            is_renamer(storage, guild_id, viewer)?
+                || guild_id.to_guild_cached(ctx).map_or(false, |guild| {
+                    guild_permissions(&guild, viewer).contains(Permissions::MANAGE_NICKNAMES)
+                })
+        }
+        None => false,
+    };
+    let response = if allowed {
+        let mut entries = storage
+            .history
+            .for_member(&guild_id, &UserId(entry.user_id))?;
+        entries.reverse();
+        let lines: Vec<String> = entries
+            .iter()
+            .take(HISTORY_PAGE_SIZE)
+            .map(describe_history_entry)
+            .collect();
+        Response::info(format!(
+            "Latest nickname changes of {}:\n{}\nSee all of them with /renamer history.",
+            UserId(entry.user_id).mention(),
+            lines.join("\n")
+        ))
+    } else {
+        Response::error(
+            "You need the Renamer role or Manage Nicknames to see other members' history.",
+        )
+    };
+    respond_privately(ctx, mci, response).await?;
+
+    Ok(())
+}
+
+/// Entries shown per page of /renamer history
+const HISTORY_PAGE_SIZE: usize = 10;
+
+/// How long the /renamer history buttons keep working
+const HISTORY_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+fn describe_history_entry(entry: &HistoryEntry) -> String {
+    let how = match (&entry.kind, entry.actor_id) {
+        (HistoryKind::Rename, Some(actor_id)) => format!("by {}", UserId(actor_id).mention()),
+        (HistoryKind::Rename, None) => "through the app".to_string(),
+        (HistoryKind::Baseline, _) => "baseline".to_string(),
+        (HistoryKind::External, _) => "outside the app".to_string(),
+    };
+    format!(
+        "<t:{}:f> {} → {} ({})",
+        entry.timestamp,
+        entry.previous_nickname.as_deref().unwrap_or("unset"),
+        entry.nickname.as_deref().unwrap_or("unset"),
+        how
+    )
+}
+
+/// Where a member's current nickname came from
+pub(super) enum Provenance {
+    NoNickname,
+    /// Set through this app by the actor, at the time
+    Managed(Option<u64>, u64),
+    /// Set directly in Discord
+    Manual,
+    /// Set before anything was recorded
+    Unknown,
+}
+
+/// Works out where a member's nickname came from, given their latest history entry
+pub(super) fn provenance(member: &Member, latest: Option<&HistoryEntry>) -> Provenance {
+    if member.nick.is_none() {
+        return Provenance::NoNickname;
+    }
+    match latest {
+        // A later change that wasn't recorded means the nickname was set some other way
+        Some(entry) if entry.nickname != member.nick => Provenance::Manual,
+        Some(HistoryEntry {
+            kind: HistoryKind::Rename,
+            actor_id,
+            timestamp,
+            ..
+        }) => Provenance::Managed(*actor_id, *timestamp),
+        Some(HistoryEntry {
+            kind: HistoryKind::External,
+            ..
+        }) => Provenance::Manual,
+        Some(HistoryEntry {
+            kind: HistoryKind::Baseline,
+            ..
+        })
+        | None => Provenance::Unknown,
+    }
+}
+
+pub(super) fn describe_provenance(provenance: &Provenance) -> String {
+    match provenance {
+        Provenance::NoNickname => "no nickname".to_string(),
+        Provenance::Managed(Some(actor_id), timestamp) => format!(
+            "set through renamer by {} <t:{}:R>",
+            UserId(*actor_id).mention(),
+            timestamp
+        ),
+        Provenance::Managed(None, timestamp) => {
+            format!("set through renamer <t:{}:R>", timestamp)
+        }
+        Provenance::Manual => "set outside renamer".to_string(),
+        Provenance::Unknown => "set before renamer recorded it".to_string(),
+    }
+}
+
+fn history_csv(entries: &[HistoryEntry]) -> String {
+    let field = |value: &str| {
+        // Members pick their own nicknames; keep spreadsheets from running them as formulas
+        let guard = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+            "'"
+        } else {
+            ""
+        };
+        format!("\"{}{}\"", guard, value.replace('"', "\"\""))
+    };
+    let mut csv = "id,timestamp,kind,actor_id,previous_nickname,nickname\n".to_string();
+    for entry in entries {
+        let kind = match entry.kind {
+            HistoryKind::Rename => "rename",
+            HistoryKind::Baseline => "baseline",
+            HistoryKind::External => "external",
+        };
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            entry.id,
+            entry.timestamp,
+            kind,
+            entry.actor_id.map(|id| id.to_string()).unwrap_or_default(),
+            field(entry.previous_nickname.as_deref().unwrap_or("")),
+            field(entry.nickname.as_deref().unwrap_or(""))
+        ));
+    }
+    csv
+}
+
+/// Anyone may see their own history; other members' needs renamer or moderator rights
+async fn may_view_history(
+    ctx: &Context<'_>,
+    viewer: &Member,
+    member: &Member,
+) -> Result<bool, Error> {
+    let storage = &ctx.data().storage;
+    if member.user.id == viewer.user.id {
+        return Ok(true);
+    }
+    let is_renamer = is_renamer(storage, member.guild_id, viewer)?;
+    let is_moderator = ctx.guild().map_or(false, |guild| {
+        guild_permissions(&guild, viewer).contains(Permissions::MANAGE_NICKNAMES)
+    });
+    Ok(is_renamer || is_moderator)
+}
+
+/// Show a member's nickname and where it came from
+#[poise::command(slash_command, category = "Member")]
+pub(super) async fn profile(
+    ctx: Context<'_>,
+    #[description = "Member whose profile to show; defaults to you"] member: Option<Member>,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    let author = ctx
+        .author_member()
+        .await
+        .ok_or::<Error>("foo".into())?
+        .into_owned();
+    let member = member.unwrap_or_else(|| author.clone());
+
+    if !may_view_history(&ctx, &author, &member).await? {
+        Response::error(
+            "You need the Renamer role or Manage Nicknames to see other members' profiles.",
+        )
+        .send(ctx)
+        .await?;
+        return Ok(());
+    }
+
+    let entries = storage.history.for_member(&guild_id, &member.user.id)?;
+    let provenance = provenance(&member, entries.last());
+    Response::info(format!(
+        "{}\nNickname: {} ({})\nRecorded changes: {}",
+        identify_member(&member),
+        member.nick.as_deref().unwrap_or("none"),
+        describe_provenance(&provenance),
+        entries.len()
+    ))
+    .send(ctx)
+    .await?;
+
+    Ok(())
+}
+
+/// Show a member's nickname history
+#[poise::command(slash_command, category = "Member")]
+pub(super) async fn history(
+    ctx: Context<'_>,
+    #[description = "Member whose history to show; defaults to you"] member: Option<Member>,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    let http = ctx.http();
+    let author = ctx
+        .author_member()
+        .await
+        .ok_or::<Error>("foo".into())?
+        .into_owned();
+    let member = member.unwrap_or_else(|| author.clone());
+
+    if !may_view_history(&ctx, &author, &member).await? {
+        Response::error(
+            "You need the Renamer role or Manage Nicknames to see other members' history.",
+        )
+        .send(ctx)
+        .await?;
+        return Ok(());
+    }
+
+    let mut entries = storage.history.for_member(&guild_id, &member.user.id)?;
+    if entries.is_empty() {
+        Response::info(format!(
+            "No nickname history is recorded for {}.",
+            identify_member(&member)
+        ))
+        .send(ctx)
+        .await?;
+        return Ok(());
+    }
+    entries.reverse();
+
+    let pages: Vec<String> = entries
+        .chunks(HISTORY_PAGE_SIZE)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .map(describe_history_entry)
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect();
+    let title = format!("Nickname history of {}", describe_member(&member));
+    let previous_id = format!("{}previous", ctx.id());
+    let next_id = format!("{}next", ctx.id());
+    let export_id = format!("{}export", ctx.id());
+
+    let mut page = 0;
+    let reply = ctx
+        .send(|m| {
+            history_page(
+                m.ephemeral(true),
+                &title,
+                &pages,
+                page,
+                [&previous_id, &next_id, &export_id],
+            )
+        })
+        .await?;
+    let message_id = reply.message().await?.id;
+
+    while let Some(mci) = CollectComponentInteraction::new(ctx.serenity_context())
+        .message_id(message_id)
+        .timeout(HISTORY_TIMEOUT)
+        .await
+    {
+        mci.create_interaction_response(http, |r| {
+            r.kind(InteractionResponseType::DeferredUpdateMessage)
+        })
+        .await?;
+
+        if mci.data.custom_id == previous_id {
+            page = page.saturating_sub(1);
+        } else if mci.data.custom_id == next_id {
+            page = (page + 1).min(pages.len() - 1);
+        } else if mci.data.custom_id == export_id {
+            let csv = history_csv(&entries);
+            ctx.send(|m| {
+                m.ephemeral(true).attachment(AttachmentType::Bytes {
+                    data: Cow::Owned(csv.into_bytes()),
+                    filename: format!("history-{}.csv", member.user.id),
+                })
+            })
+            .await?;
+            continue;
+        }
+        reply
+            .edit(ctx, |m| {
+                history_page(
+                    m,
+                    &title,
+                    &pages,
+                    page,
+                    [&previous_id, &next_id, &export_id],
+                )
+            })
+            .await?;
+    }
+
+    reply.edit(ctx, |m| m.components(|c| c)).await?;
+
+    Ok(())
+}
+
+fn history_page<'a, 'b>(
+    m: &'b mut poise::CreateReply<'a>,
+    title: &str,
+    pages: &[String],
+    page: usize,
+    [previous_id, next_id, export_id]: [&String; 3],
+) -> &'b mut poise::CreateReply<'a> {
+    m.embed(|e| {
+        e.title(title)
+            .description(&pages[page])
+            .footer(|f| f.text(format!("Page {}/{}", page + 1, pages.len())))
+    })
+    .components(|c| {
+        c.create_action_row(|r| {
+            r.create_button(|b| {
+                b.custom_id(previous_id)
+                    .label("Previous")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(page == 0)
+            })
+            .create_button(|b| {
+                b.custom_id(next_id)
+                    .label("Next")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(page + 1 == pages.len())
+            })
+            .create_button(|b| {
+                b.custom_id(export_id)
+                    .label("Export")
+                    .style(ButtonStyle::Primary)
+            })
+        })
+    })
+}
+
+/// Get a DM whenever a member's nickname changes in this server
+#[poise::command(
+    slash_command,
+    required_permissions = "MANAGE_NICKNAMES",
+    category = "Moderator"
+)]
+pub(super) async fn watch(
+    ctx: Context<'_>,
+    #[description = "Member to watch"] member: Member,
+    #[description = "Stop watching the member instead"] stop: Option<bool>,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    let watcher_id = ctx.author().id;
+
+    let response = if stop.unwrap_or(false) {
+        if storage
+            .watches
+            .remove(&guild_id, &member.user.id, &watcher_id)?
+        {
+            Response::success(format!("Stopped watching {}.", identify_member(&member)))
+        } else {
+            Response::info(format!(
+                "You weren't watching {}.",
+                identify_member(&member)
+            ))
+        }
+    } else if storage
+        .watches
+        .insert(&guild_id, &member.user.id, &watcher_id)?
+    {
+        Response::info(format!(
+            "You are already watching {}.",
+            identify_member(&member)
+        ))
+    } else {
+        Response::success(format!(
+            "You'll get a DM whenever the nickname of {} changes.",
+            identify_member(&member)
+        ))
+    };
+    response.send(ctx).await?;
+
+    Ok(())
+}
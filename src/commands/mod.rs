@@ -0,0 +1,550 @@
+//! Slash and prefix commands, grouped into modules by what they do
+//!
+//! Helpers shared by several groups live here; state lives in [`Storage`], which every
+//! command reaches through [`Data`].
+
+mod admin;
+mod events;
+mod help;
+mod history;
+mod optin;
+mod rename;
+mod sweep;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::TryStreamExt;
+use poise::serenity_prelude as serenity;
+use poise::serenity_prelude::http::routing::Route;
+use poise::serenity_prelude::{
+    ButtonStyle, CacheHttp, CollectComponentInteraction, GatewayIntents, Guild, GuildId, Http,
+    InteractionResponseType, Member, Mentionable, Permissions, RoleId, UserId,
+};
+use poise::ReplyHandle;
+use tracing::warn;
+
+use self::admin::admin;
+use self::help::help;
+use self::history::{history, profile, watch};
+use self::optin::{allow, disallow, forget_me, my_data, optin, optout};
+use self::rename::{random, request, status, transliterate};
+use crate::response::Response;
+use crate::services::dm_limiter::DmLimiter;
+use crate::services::storage::AppRole::*;
+use crate::services::storage::Setting::*;
+use crate::services::storage::{AppRole, Storage};
+use crate::services::validation::Report;
+
+pub(crate) use self::admin::{migrations, usage};
+pub(crate) use self::events::{
+    command_enabled, event_handler, expire_staff_approvals, log_events, notify_watchers, on_error,
+    record_invocation,
+};
+pub(crate) use self::optin::process_forget_requests;
+pub(crate) use self::rename::rename;
+pub(crate) use self::sweep::sweep_nicknames;
+
+/// What server boosters may do to themselves without the Renamer role
+#[derive(poise::ChoiceParameter, Clone, Copy, PartialEq, Eq, PartialOrd)]
+enum BoosterPerk {
+    #[name = "off"]
+    Off,
+    /// /rename themselves
+    #[name = "self_rename"]
+    SelfRename,
+    /// Also /renamer transliterate and /renamer random themselves
+    #[name = "styling"]
+    Styling,
+}
+
+impl BoosterPerk {
+    fn label(&self) -> &'static str {
+        match self {
+            BoosterPerk::Off => "off",
+            BoosterPerk::SelfRename => "self_rename",
+            BoosterPerk::Styling => "styling",
+        }
+    }
+}
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub(crate) struct Data {
+    pub(crate) storage: Arc<Storage>,
+    pub(crate) dm_limiter: DmLimiter,
+    /// How long /renamer forget_me waits before deleting a user's data
+    pub(crate) forget_me_grace_period: Duration,
+    /// Privileged intents that aren't enabled in the developer portal, so the app
+    /// connected without them
+    pub(crate) missing_intents: GatewayIntents,
+}
+
+pub(crate) type Error = Box<dyn std::error::Error + Send + Sync>;
+
+type Context<'a> = poise::Context<'a, Data, Error>;
+
+macro_rules! role_by_name {
+    ($guild_id:expr, $http:expr, $name:expr) => {{
+        let guild_id: &GuildId = &$guild_id;
+        let name_: &str = &$name;
+        let http_: &Http = $http;
+        guild_id
+            .roles(http_)
+            .await
+            .unwrap()
+            .values()
+            .find(|role| name_ == role.name)
+    }};
+}
+
+pub(crate) use role_by_name;
+
+/// Whether the member boosts the server and the server grants boosters the given perk
+fn has_booster_perk(ctx: &Context<'_>, member: &Member, perk: BoosterPerk) -> Result<bool, Error> {
+    let storage = &ctx.data().storage;
+    let granted = match storage
+        .settings
+        .get(BoosterPerkTier, &member.guild_id)?
+        .as_deref()
+    {
+        Some("self_rename") => BoosterPerk::SelfRename,
+        Some("styling") => BoosterPerk::Styling,
+        _ => BoosterPerk::Off,
+    };
+    if perk == BoosterPerk::Off || granted < perk {
+        return Ok(false);
+    }
+
+    // Discord gives boosters a managed role tagged as the premium subscriber role
+    let has_booster_role = ctx.guild().map_or(false, |guild| {
+        guild
+            .roles
+            .values()
+            .any(|role| role.tags.premium_subscriber && member.roles.contains(&role.id))
+    });
+    Ok(has_booster_role || member.premium_since.is_some())
+}
+
+async fn check_set_up(ctx: &Context<'_>, app_role: AppRole) -> Result<Option<RoleId>, Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    let http = ctx.http();
+
+    let role_id = storage.roles.get(app_role, &guild_id)?;
+
+    let result = if let Some(role_id) = role_id {
+        if let Some(role) = guild_id.roles(http).await?.get(&role_id) {
+            // match app_role {
+            //     Renamer => {
+            //         if role.has_permission(Permissions::MANAGE_NICKNAMES) {
+            //             Ok(role.id)
+            //         } else {
+            //             Err(format!("{} role does not have the right permissions", app_role))
+            //         }
+            //     }
+            //     Allow => Ok(role.id)
+            // }
+            Ok(role.id)
+        } else {
+            Err(format!("{} role does not exist in this server", app_role))
+        }
+    } else {
+        Err(format!("{} role not known for this server", app_role))
+    };
+
+    match result {
+        Ok(role_id) => Ok(Some(role_id)),
+        Err(msg_text) => {
+            Response::error(format!(
+                "{}. Have an admin set up the app with /renamer admin set_roles.",
+                msg_text
+            ))
+            .send(*ctx)
+            .await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Explains that the command needs the Server Members intent if the app runs without it,
+/// since Discord refuses to list members then. Returns whether the intent is enabled.
+async fn check_members_intent(ctx: &Context<'_>) -> Result<bool, Error> {
+    if !ctx
+        .data()
+        .missing_intents
+        .contains(GatewayIntents::GUILD_MEMBERS)
+    {
+        return Ok(true);
+    }
+    Response::error(
+        "This needs the Server Members intent, which is disabled for this app. Ask its owner to enable it under Bot > Privileged Gateway Intents in the developer portal.",
+    )
+    .send(*ctx)
+    .await?;
+    Ok(false)
+}
+
+/// Fetches every member of the guild, paging through the member list as needed. Needs
+/// the Server Members intent; see [`check_members_intent`].
+async fn guild_members(ctx: &Context<'_>) -> Result<Vec<Member>, Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let members: Vec<Member> = guild_id.members_iter(ctx.http()).try_collect().await?;
+    Ok(members)
+}
+
+/// How often a progress message may be edited, to stay clear of Discord's rate limits
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// An ephemeral response that reports how far a long-running operation has got.
+///
+/// Starting one responds to the interaction straight away, so operations that outlast
+/// Discord's three-second response window don't show "application did not respond".
+/// The response can only be edited until the interaction token expires after 15 minutes,
+/// so failed edits don't stop the operation.
+struct Progress<'a> {
+    ctx: Context<'a>,
+    reply: ReplyHandle<'a>,
+    action: String,
+    last_update: Instant,
+    /// Set once an edit fails, after which later edits would fail too
+    expired: bool,
+}
+
+impl<'a> Progress<'a> {
+    async fn start(ctx: Context<'a>, action: &str) -> Result<Progress<'a>, Error> {
+        let reply = ctx
+            .send(|m| m.ephemeral(true).content(format!("{}…", action)))
+            .await?;
+        Ok(Self {
+            ctx,
+            reply,
+            action: action.to_string(),
+            last_update: Instant::now(),
+            expired: false,
+        })
+    }
+
+    async fn update(&mut self, done: usize, total: usize) {
+        if self.expired || self.last_update.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        self.last_update = Instant::now();
+
+        let content = format!("{} {}/{}…", self.action, done, total);
+        if let Err(e) = self.reply.edit(self.ctx, |m| m.content(content)).await {
+            warn!("Failed to update progress of {}: {}", self.action, e);
+            self.expired = true;
+        }
+    }
+
+    /// Shows the result in the response, or in the channel if the response can no
+    /// longer be edited
+    async fn finish(self, msg: &str) -> Result<(), Error> {
+        if !self.expired && self.reply.edit(self.ctx, |m| m.content(msg)).await.is_ok() {
+            return Ok(());
+        }
+        self.ctx
+            .channel_id()
+            .say(
+                self.ctx.http(),
+                format!("{}: {}", self.ctx.author().mention(), msg),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+/// Asks the author to confirm a destructive action with buttons, returning whether they did
+async fn confirm(ctx: &Context<'_>, prompt: &str) -> Result<bool, Error> {
+    let confirm_id = format!("{}confirm", ctx.id());
+    let cancel_id = format!("{}cancel", ctx.id());
+
+    let reply = ctx
+        .send(|m| {
+            m.ephemeral(true).content(prompt).components(|c| {
+                c.create_action_row(|r| {
+                    r.create_button(|b| {
+                        b.custom_id(&confirm_id)
+                            .label("Confirm")
+                            .style(ButtonStyle::Danger)
+                    })
+                    .create_button(|b| {
+                        b.custom_id(&cancel_id)
+                            .label("Cancel")
+                            .style(ButtonStyle::Secondary)
+                    })
+                })
+            })
+        })
+        .await?;
+
+    let filter_ids = [confirm_id.clone(), cancel_id];
+    let interaction = CollectComponentInteraction::new(ctx.serenity_context())
+        .author_id(ctx.author().id)
+        .channel_id(ctx.channel_id())
+        .timeout(Duration::from_secs(60))
+        .filter(move |mci| filter_ids.contains(&mci.data.custom_id))
+        .await;
+
+    let confirmed = match interaction {
+        Some(mci) => {
+            mci.create_interaction_response(ctx.http(), |r| {
+                r.kind(InteractionResponseType::DeferredUpdateMessage)
+            })
+            .await?;
+            mci.data.custom_id == confirm_id
+        }
+        None => false,
+    };
+
+    reply
+        .edit(*ctx, |m| {
+            if !confirmed {
+                m.content("Cancelled; no change made.");
+            }
+            m.components(|c| c)
+        })
+        .await?;
+
+    Ok(confirmed)
+}
+
+/// Appends any validation warnings to a reply
+fn with_warnings(response: Response, report: &Report) -> Response {
+    let warnings = report.warnings();
+    if warnings.is_empty() {
+        response
+    } else {
+        response.warn(&format!("The nickname {}.", warnings.join(", ")))
+    }
+}
+
+fn nickname_applied(member: &Member, nickname: &str) -> bool {
+    // Discord trims leading and trailing whitespace from nicknames it stores
+    member.nick.as_deref().map(str::trim) == Some(nickname.trim())
+}
+
+/// The name shown for a member in the server: their nickname, else their global display
+/// name, else their username
+fn display_name(member: &Member) -> &str {
+    member
+        .nick
+        .as_deref()
+        .or(member.user.global_name.as_deref())
+        .unwrap_or(&member.user.name)
+}
+
+/// Formats a member as `display name (username)`, or just the username if they're the same
+fn describe_member(member: &Member) -> String {
+    let display_name = display_name(member);
+    if display_name == member.user.name {
+        member.user.name.clone()
+    } else {
+        format!("{} ({})", display_name, member.user.name)
+    }
+}
+
+/// Identifies a member unambiguously with a clickable mention, their username and their
+/// user ID, so moderators can tell apart members with similar names
+fn identify_member(member: &Member) -> String {
+    format!(
+        "{} ({}, ID {})",
+        member.mention(),
+        member.user.name,
+        member.user.id
+    )
+}
+
+/// The permissions a member has in the server as a whole, ignoring channel overwrites
+fn guild_permissions(guild: &Guild, member: &Member) -> Permissions {
+    if member.user.id == guild.owner_id {
+        return Permissions::all();
+    }
+    let permissions = guild
+        .roles
+        .get(&RoleId(guild.id.0))
+        .into_iter()
+        .chain(member.roles.iter().filter_map(|id| guild.roles.get(id)))
+        .fold(Permissions::empty(), |permissions, role| {
+            permissions | role.permissions
+        });
+    if permissions.contains(Permissions::ADMINISTRATOR) {
+        Permissions::all()
+    } else {
+        permissions
+    }
+}
+
+fn highest_role_position(guild: &Guild, member: &Member) -> i64 {
+    member
+        .roles
+        .iter()
+        .filter_map(|id| guild.roles.get(id))
+        .map(|role| role.position)
+        .max()
+        .unwrap_or(0)
+}
+
+/// What a button or modal on one of the app's messages does. It is encoded in the
+/// component's custom ID, so it still works after a restart.
+pub(crate) enum ComponentAction {
+    ReviewStaffApproval {
+        id: u64,
+        approve: bool,
+    },
+    ReviewNicknameRequest {
+        id: u64,
+        approve: bool,
+    },
+    /// Asks for another nickname for the member renamed in a history entry
+    RenameAgain(u64),
+    /// Restores the nickname a history entry replaced
+    Undo(u64),
+    /// Shows the nickname history of the member renamed in a history entry
+    ViewHistory(u64),
+    /// Gives the Allow role to a member invited by a consent campaign
+    ConsentOptIn(u64),
+    /// Strips the offending parts from a member's nickname listed in a sweep report
+    FixNickname(u64),
+}
+
+impl ComponentAction {
+    pub(crate) fn custom_id(&self) -> String {
+        match self {
+            ComponentAction::ReviewStaffApproval { id, approve } => format!(
+                "staff_approval:{}:{}",
+                id,
+                if *approve { "approve" } else { "deny" }
+            ),
+            ComponentAction::ReviewNicknameRequest { id, approve } => format!(
+                "nickname_request:{}:{}",
+                id,
+                if *approve { "approve" } else { "deny" }
+            ),
+            ComponentAction::RenameAgain(id) => format!("rename_again:{}", id),
+            ComponentAction::Undo(id) => format!("undo:{}", id),
+            ComponentAction::ViewHistory(id) => format!("view_history:{}", id),
+            ComponentAction::ConsentOptIn(id) => format!("consent:{}", id),
+            ComponentAction::FixNickname(user_id) => format!("fix_nickname:{}", user_id),
+        }
+    }
+
+    fn parse(custom_id: &str) -> Option<Self> {
+        let (action, rest) = custom_id.split_once(':')?;
+        match action {
+            "staff_approval" => {
+                let (id, decision) = rest.split_once(':')?;
+                Some(ComponentAction::ReviewStaffApproval {
+                    id: id.parse().ok()?,
+                    approve: decision == "approve",
+                })
+            }
+            "nickname_request" => {
+                let (id, decision) = rest.split_once(':')?;
+                Some(ComponentAction::ReviewNicknameRequest {
+                    id: id.parse().ok()?,
+                    approve: decision == "approve",
+                })
+            }
+            "rename_again" => Some(ComponentAction::RenameAgain(rest.parse().ok()?)),
+            "undo" => Some(ComponentAction::Undo(rest.parse().ok()?)),
+            "view_history" => Some(ComponentAction::ViewHistory(rest.parse().ok()?)),
+            "consent" => Some(ComponentAction::ConsentOptIn(rest.parse().ok()?)),
+            "fix_nickname" => Some(ComponentAction::FixNickname(rest.parse().ok()?)),
+            _ => None,
+        }
+    }
+}
+
+/// Whether the member has this guild's Renamer role
+fn is_renamer(storage: &Storage, guild_id: GuildId, member: &Member) -> Result<bool, Error> {
+    Ok(match storage.roles.get(Renamer, &guild_id)? {
+        Some(role_id) => member.roles.contains(&role_id),
+        None => false,
+    })
+}
+
+fn is_staff(storage: &Storage, member: &Member) -> Result<bool, Error> {
+    Ok(staff_roles(storage, &member.guild_id)?
+        .iter()
+        .any(|role_id| member.roles.contains(role_id)))
+}
+
+fn staff_roles(storage: &Storage, guild_id: &GuildId) -> Result<Vec<RoleId>, Error> {
+    Ok(storage
+        .settings
+        .get(StaffRoles, guild_id)?
+        .map(|ids| {
+            ids.split(',')
+                .filter_map(|id| id.parse().ok().map(RoleId))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+async fn respond_privately(
+    ctx: &serenity::Context,
+    mci: &serenity::MessageComponentInteraction,
+    response: Response,
+) -> Result<(), Error> {
+    mci.create_interaction_response(&ctx.http, |r| {
+        r.kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|d| response.build_interaction(d))
+    })
+    .await?;
+    Ok(())
+}
+
+/// Why Discord won't let the app rename a member, if it can be told in advance
+fn rename_blocker(guild: &Guild, bot_id: UserId, target_member: &Member) -> Option<String> {
+    if target_member.user.id == guild.owner_id {
+        return Some(format!(
+            "{} owns this server, and Discord doesn't let apps rename server owners.",
+            identify_member(target_member)
+        ));
+    }
+    let bot_member = guild.members.get(&bot_id)?;
+    if highest_role_position(guild, target_member) >= highest_role_position(guild, bot_member) {
+        return Some(format!(
+            "{} has a role at or above the app's highest role, so Discord won't let the app rename them. Have an admin move the app's role higher.",
+            identify_member(target_member)
+        ));
+    }
+    None
+}
+
+#[poise::command(
+    slash_command,
+    subcommands(
+        "help",
+        "allow",
+        "disallow",
+        "optout",
+        "optin",
+        "forget_me",
+        "my_data",
+        "history",
+        "profile",
+        "request",
+        "watch",
+        "status",
+        "transliterate",
+        "random",
+        "admin"
+    )
+)]
+pub(crate) async fn renamer(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// How long until Discord accepts another request on the route, if it's used up
+async fn rate_limit_wait(http: &Http, route: Route) -> Option<Duration> {
+    let routes = http.ratelimiter.routes();
+    let routes = routes.read().await;
+    let ratelimit = routes.get(&route)?.lock().await;
+    if ratelimit.remaining() > 0 {
+        return None;
+    }
+    ratelimit.reset_after()
+}
@@ -0,0 +1,297 @@
+//! Commands members use to decide whether they can be renamed and what is kept about
+//! them, and the jobs that carry out those decisions
+
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Duration;
+
+use poise::serenity_prelude as serenity;
+use poise::serenity_prelude::{AttachmentType, CacheHttp, GuildId, UserId};
+use serde_json::json;
+use tracing::{error, info};
+
+use super::{check_set_up, confirm, respond_privately, Context, Error};
+use crate::response::Response;
+use crate::services::storage::AppRole::*;
+use crate::services::storage::{unix_now, Storage};
+
+/// Gives the Allow role to whoever pressed a consent campaign's opt-in button
+pub(super) async fn consent_opt_in(
+    storage: &Storage,
+    ctx: &serenity::Context,
+    mci: &serenity::MessageComponentInteraction,
+    campaign_id: u64,
+) -> Result<(), Error> {
+    let Some(campaign) = storage.consent_campaigns.get(campaign_id)? else {
+        respond_privately(ctx, mci, Response::error("This invitation has expired.")).await?;
+        return Ok(());
+    };
+    let guild_id = GuildId(campaign.guild_id);
+    let allow_role = match storage.roles.get(Allow, &guild_id)? {
+        Some(role_id) => guild_id.roles(&ctx.http).await?.remove(&role_id),
+        None => None,
+    };
+    let Some(allow_role) = allow_role else {
+        respond_privately(
+            ctx,
+            mci,
+            Response::error("The server no longer has an Allow role; ask an admin about it."),
+        )
+        .await?;
+        return Ok(());
+    };
+    let Ok(mut member) = guild_id.member(ctx, mci.user.id).await else {
+        respond_privately(
+            ctx,
+            mci,
+            Response::error("You are no longer in that server."),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let response = if member.roles.contains(&allow_role.id) {
+        Response::info("You are already allowing nickname changes.")
+    } else {
+        member.add_role(&ctx.http, allow_role.id).await?;
+        storage.consent_campaigns.record_opt_in(campaign_id)?;
+        let response = Response::success(format!(
+            "Successfully allowed nickname changes in {}. Use /renamer disallow there to stop.",
+            guild_id.name(ctx).unwrap_or_else(|| "the server".into())
+        ));
+        if storage.optouts.contains(&mci.user.id)? {
+            response
+                .warn("You have opted out in every server; use /renamer optin global to undo that.")
+        } else {
+            response
+        }
+    };
+    respond_privately(ctx, mci, response).await?;
+
+    Ok(())
+}
+
+/// Deletes everything stored about a user
+fn forget_user(storage: &Storage, user_id: &UserId) -> Result<(), Error> {
+    storage.history.remove_involving(user_id)?;
+    storage.watches.remove_involving(user_id)?;
+    storage.staff_approvals.remove_involving(user_id)?;
+    storage.optouts.remove(user_id)?;
+    storage.forget_requests.remove(user_id)?;
+    Ok(())
+}
+
+/// Periodically deletes the data of users whose forget_me grace period has passed
+pub(crate) async fn process_forget_requests(storage: Arc<Storage>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(10 * 60));
+    loop {
+        interval.tick().await;
+        if let Err(e) = forget_due_users(&storage) {
+            error!("Failed to process data deletion requests: {}", e);
+        }
+    }
+}
+
+fn forget_due_users(storage: &Storage) -> Result<(), Error> {
+    for user_id in storage.forget_requests.due(unix_now())? {
+        forget_user(storage, &user_id)?;
+        info!("Deleted stored data for user {}", user_id);
+    }
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    required_bot_permissions = "MANAGE_ROLES",
+    category = "Member"
+)]
+pub(super) async fn allow(ctx: Context<'_>) -> Result<(), Error> {
+    let mut member_cow = ctx.author_member().await.ok_or::<Error>("foo".into())?;
+    let member = member_cow.to_mut();
+    let guild_id = ctx.guild_id().unwrap();
+    let http = ctx.http();
+
+    if let Some(allow_role_id) = check_set_up(&ctx, Allow).await? {
+        let response = if !member.user.has_role(http, guild_id, allow_role_id).await? {
+            member.add_role(http, allow_role_id).await?;
+            Response::success("Successfully allowed nickname changes.")
+        } else {
+            Response::info("You are already allowing nickname changes.")
+        };
+        response.send(ctx).await?;
+    }
+
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    required_bot_permissions = "MANAGE_ROLES",
+    category = "Member"
+)]
+pub(super) async fn disallow(ctx: Context<'_>) -> Result<(), Error> {
+    let mut member_cow = ctx.author_member().await.ok_or::<Error>("foo".into())?;
+    let member = member_cow.to_mut();
+    let guild_id = ctx.guild_id().unwrap();
+    let http = ctx.http();
+
+    if let Some(allow_role_id) = check_set_up(&ctx, Allow).await? {
+        let response = if member.user.has_role(http, guild_id, allow_role_id).await? {
+            member.remove_role(http, allow_role_id).await?;
+            Response::success("Successfully disallowed nickname changes.")
+        } else {
+            Response::info("You are already disallowing nickname changes.")
+        };
+        response.send(ctx).await?;
+    }
+
+    Ok(())
+}
+
+#[poise::command(slash_command, subcommands("optout_global"))]
+pub(super) async fn optout(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Stop renamer from changing your nickname in every server, including this one
+#[poise::command(slash_command, rename = "global", category = "Member")]
+async fn optout_global(ctx: Context<'_>) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let response = if storage.optouts.insert(&ctx.author().id)? {
+        Response::info("You have already opted out of nickname changes in every server.")
+    } else {
+        Response::success("Successfully opted out of nickname changes in every server.")
+    };
+    response.send(ctx).await?;
+
+    Ok(())
+}
+
+#[poise::command(slash_command, subcommands("optin_global"))]
+pub(super) async fn optin(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Undo a global opt-out; each server's Allow role still applies
+#[poise::command(slash_command, rename = "global", category = "Member")]
+async fn optin_global(ctx: Context<'_>) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let response = if storage.optouts.remove(&ctx.author().id)? {
+        Response::success("Successfully removed your global opt-out.")
+    } else {
+        Response::info("You have not opted out globally.")
+    };
+    response.send(ctx).await?;
+
+    Ok(())
+}
+
+/// Delete everything renamer stores about you, including your global opt-out
+#[poise::command(slash_command, category = "Member")]
+pub(super) async fn forget_me(
+    ctx: Context<'_>,
+    #[description = "Cancel a pending deletion instead of requesting one"] cancel: Option<bool>,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let user_id = ctx.author().id;
+
+    if cancel.unwrap_or(false) {
+        let response = if storage.forget_requests.remove(&user_id)? {
+            Response::success("Cancelled your pending data deletion.")
+        } else {
+            Response::info("You have no pending data deletion.")
+        };
+        response.send(ctx).await?;
+        return Ok(());
+    }
+
+    let grace_period = ctx.data().forget_me_grace_period;
+    let prompt = if grace_period.is_zero() {
+        "Delete all data renamer stores about you, including your global opt-out? \
+        This cannot be undone."
+            .to_string()
+    } else {
+        format!(
+            "Delete all data renamer stores about you, including your global opt-out? \
+            Deletion happens after a grace period of {} hour(s), during which you can cancel.",
+            grace_period.as_secs() / 3600
+        )
+    };
+    if !confirm(&ctx, &prompt).await? {
+        return Ok(());
+    }
+
+    let response = if grace_period.is_zero() {
+        forget_user(storage, &user_id)?;
+        Response::success("Deleted all data renamer stored about you.")
+    } else {
+        storage
+            .forget_requests
+            .insert(&user_id, unix_now() + grace_period.as_secs())?;
+        Response::success(
+            "Your data will be deleted once the grace period ends. \
+            Use /renamer forget_me cancel:True to keep it.",
+        )
+    };
+    response.send(ctx).await?;
+
+    Ok(())
+}
+
+/// Get a copy of everything renamer stores about you in a DM
+#[poise::command(slash_command, category = "Member")]
+pub(super) async fn my_data(ctx: Context<'_>) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let user_id = ctx.author().id;
+
+    let export = json!({
+        "user_id": user_id.to_string(),
+        "history": storage.history.involving(&user_id)?,
+        "watches": storage.watches
+            .involving(&user_id)?
+            .into_iter()
+            .map(|(guild_id, member_id, watcher_id)| json!({
+                "guild_id": guild_id.to_string(),
+                "member_id": member_id.to_string(),
+                "watcher_id": watcher_id.to_string(),
+            }))
+            .collect::<Vec<_>>(),
+        "pending_approvals": storage.staff_approvals
+            .involving(&user_id)?
+            .into_iter()
+            .map(|(_, approval)| json!({
+                "kind": approval.kind,
+                "guild_id": approval.guild_id.to_string(),
+                "actor_id": approval.actor_id.to_string(),
+                "target_id": approval.target_id.to_string(),
+                "nickname": approval.nickname,
+                "expires_at": approval.expires_at,
+            }))
+            .collect::<Vec<_>>(),
+        "global_optout": storage.optouts.contains(&user_id)?,
+        "pending_deletion_due_at": storage.forget_requests.get(&user_id)?,
+    });
+    let data = serde_json::to_vec_pretty(&export)?;
+
+    let dm_result = ctx
+        .author()
+        .direct_message(ctx.http(), |m| {
+            m.content("Here is everything renamer stores about you.")
+                .add_file(AttachmentType::Bytes {
+                    data: Cow::Owned(data),
+                    filename: "renamer_data.json".into(),
+                })
+        })
+        .await;
+
+    let response = if dm_result.is_ok() {
+        Response::success("Sent your data in a DM.")
+    } else {
+        Response::error(
+            "Couldn't send you a DM. Check that your privacy settings allow DMs from this server.",
+        )
+    };
+    response.send(ctx).await?;
+
+    Ok(())
+}
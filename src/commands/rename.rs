@@ -0,0 +1,1165 @@
+//! Renaming members, directly or through requests and staff approvals, and the buttons
+//! that follow a rename
+
+use std::time::Duration;
+
+use poise::serenity_prelude as serenity;
+use poise::serenity_prelude::http::routing::Route;
+use poise::serenity_prelude::{
+    ButtonStyle, CacheHttp, GuildId, InteractionResponseType, Member, Mentionable, UserId,
+};
+use tracing::info;
+
+use super::{
+    check_set_up, confirm, describe_member, display_name, has_booster_perk, identify_member,
+    is_renamer, is_staff, nickname_applied, rename_blocker, respond_privately, with_warnings,
+    BoosterPerk, ComponentAction, Context, Error,
+};
+use crate::response::Response;
+use crate::services::storage::AppRole::*;
+use crate::services::storage::Setting::*;
+use crate::services::storage::{
+    unix_now, ApprovalKind, HistoryEntry, HistoryKind, PendingApproval, Storage,
+};
+use crate::services::validation::validate_nickname;
+use crate::{random_name, transform};
+
+/// How /rename's `username` finds the member to rename
+#[derive(poise::ChoiceParameter, Clone, Copy, PartialEq, Eq)]
+pub(super) enum LookupMode {
+    /// Username, nickname or display name starts with the query
+    #[name = "prefix"]
+    Prefix,
+    #[name = "exact_username"]
+    ExactUsername,
+    /// Nickname, else global display name, else username is the query
+    #[name = "exact_display_name"]
+    ExactDisplayName,
+    /// Any name contains the query, keeping only the closest matches
+    #[name = "fuzzy"]
+    Fuzzy,
+    /// A user ID or mention
+    #[name = "id"]
+    Id,
+}
+
+impl LookupMode {
+    pub(super) fn label(&self) -> &'static str {
+        match self {
+            LookupMode::Prefix => "prefix",
+            LookupMode::ExactUsername => "exact_username",
+            LookupMode::ExactDisplayName => "exact_display_name",
+            LookupMode::Fuzzy => "fuzzy",
+            LookupMode::Id => "id",
+        }
+    }
+
+    fn for_guild(storage: &Storage, guild_id: &GuildId) -> Result<Self, Error> {
+        Ok(
+            match storage.settings.get(MemberLookup, guild_id)?.as_deref() {
+                Some("exact_username") => LookupMode::ExactUsername,
+                Some("exact_display_name") => LookupMode::ExactDisplayName,
+                Some("fuzzy") => LookupMode::Fuzzy,
+                Some("id") => LookupMode::Id,
+                _ => LookupMode::Prefix,
+            },
+        )
+    }
+
+    /// Whether a single result can only be the member that was meant
+    fn is_exact(&self) -> bool {
+        matches!(
+            self,
+            LookupMode::ExactUsername | LookupMode::ExactDisplayName | LookupMode::Id
+        )
+    }
+}
+
+/// Finds the members `query` refers to, the way the guild has chosen
+async fn lookup_members(
+    ctx: &Context<'_>,
+    query: &str,
+    mode: LookupMode,
+) -> Result<Vec<Member>, Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let lowercase_query = query.trim().to_lowercase();
+
+    let members = match mode {
+        LookupMode::Prefix => search_members(ctx, query).await?,
+        LookupMode::ExactUsername => search_members(ctx, query)
+            .await?
+            .into_iter()
+            .filter(|member| member.user.name.to_lowercase() == lowercase_query)
+            .collect(),
+        LookupMode::ExactDisplayName => search_members(ctx, query)
+            .await?
+            .into_iter()
+            .filter(|member| display_name(member).to_lowercase() == lowercase_query)
+            .collect(),
+        LookupMode::Fuzzy => {
+            let mut candidates = search_members(ctx, query).await?;
+            if let Some(guild) = ctx.guild() {
+                for member in guild.members.into_values() {
+                    if !candidates.iter().any(|m| m.user.id == member.user.id) {
+                        candidates.push(member);
+                    }
+                }
+            }
+            let scored: Vec<(f64, Member)> = candidates
+                .into_iter()
+                .map(|member| (fuzzy_similarity(&lowercase_query, &member), member))
+                .filter(|(score, _)| *score > 0.0)
+                .collect();
+            let best = scored.iter().map(|(score, _)| *score).fold(0.0, f64::max);
+            scored
+                .into_iter()
+                .filter(|(score, _)| *score == best)
+                .map(|(_, member)| member)
+                .collect()
+        }
+        LookupMode::Id => {
+            let id = query
+                .trim()
+                .trim_start_matches("<@")
+                .trim_start_matches('!')
+                .trim_end_matches('>')
+                .parse::<u64>();
+            match id {
+                Ok(id) => guild_id
+                    .member(ctx.serenity_context(), id)
+                    .await
+                    .into_iter()
+                    .collect(),
+                Err(_) => Vec::new(),
+            }
+        }
+    };
+
+    Ok(members)
+}
+
+/// How much of a member's closest name a lowercase query covers, from 0 (not contained in
+/// any of them) to 1 (an exact match)
+fn fuzzy_similarity(query: &str, member: &Member) -> f64 {
+    [
+        Some(&member.user.name),
+        member.user.global_name.as_ref(),
+        member.nick.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|name| {
+        let name = name.to_lowercase();
+        if name.contains(query) {
+            query.chars().count() as f64 / name.chars().count() as f64
+        } else {
+            0.0
+        }
+    })
+    .fold(0.0, f64::max)
+}
+
+/// Finds members whose username, nickname or global display name starts with `query`
+async fn search_members(ctx: &Context<'_>, query: &str) -> Result<Vec<Member>, Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let mut members = guild_id.search_members(ctx.http(), query, None).await?;
+
+    // Discord's member search only covers usernames and nicknames, so global display
+    // names have to be matched against the cached member list
+    let query = query.trim().to_lowercase();
+    if let Some(guild) = ctx.guild() {
+        for member in guild.members.into_values() {
+            let display_name_matches = member
+                .user
+                .global_name
+                .as_ref()
+                .is_some_and(|name| name.to_lowercase().starts_with(&query));
+            if display_name_matches && !members.iter().any(|m| m.user.id == member.user.id) {
+                members.push(member);
+            }
+        }
+    }
+
+    Ok(members)
+}
+
+/// Percentage of a member's name that a search must match to skip confirmation
+const DEFAULT_MATCH_THRESHOLD: f64 = 100.0;
+
+/// How much of a member's username, display name or nickname a search query covers, from 0 (not a
+/// prefix of either) to 1 (an exact, case-insensitive match)
+fn match_similarity(query: &str, member: &Member) -> f64 {
+    let query = query.trim().to_lowercase();
+    [
+        Some(&member.user.name),
+        member.user.global_name.as_ref(),
+        member.nick.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|name| {
+        let name = name.to_lowercase();
+        if name.starts_with(&query) {
+            query.chars().count() as f64 / name.chars().count() as f64
+        } else {
+            0.0
+        }
+    })
+    .fold(0.0, f64::max)
+}
+
+/// How long a staff rename waits for a second renamer's approval
+const STAFF_APPROVAL_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Renames a member, or asks for a second renamer's approval first if they are staff
+async fn rename_member(
+    ctx: &Context<'_>,
+    actor: &Member,
+    target_member: &Member,
+    nickname: &str,
+) -> Result<Response, Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    if !is_staff(storage, target_member)? || actor.user.id == target_member.user.id {
+        return set_nickname(
+            storage,
+            ctx.serenity_context(),
+            guild_id,
+            actor,
+            target_member,
+            nickname,
+        )
+        .await;
+    }
+
+    let id = storage.staff_approvals.insert(&PendingApproval {
+        guild_id: guild_id.0,
+        actor_id: actor.user.id.0,
+        target_id: target_member.user.id.0,
+        nickname: nickname.to_string(),
+        expires_at: unix_now() + STAFF_APPROVAL_TIMEOUT.as_secs(),
+        message: None,
+        kind: ApprovalKind::StaffRename,
+    })?;
+    let reply = ctx.send(|m| {
+        m.content(format!(
+            "{} wants to set the nickname of staff member {} to {}. Another renamer needs to approve this.",
+            describe_member(actor),
+            identify_member(target_member),
+            nickname
+        ))
+        .components(|c| {
+            c.create_action_row(|r| {
+                r.create_button(|b| {
+                    b.custom_id(
+                        ComponentAction::ReviewStaffApproval { id, approve: true }.custom_id(),
+                    )
+                        .label("Approve")
+                        .style(ButtonStyle::Success)
+                })
+                .create_button(|b| {
+                    b.custom_id(
+                        ComponentAction::ReviewStaffApproval { id, approve: false }.custom_id(),
+                    )
+                        .label("Deny")
+                        .style(ButtonStyle::Danger)
+                })
+            })
+        })
+    })
+    .await?;
+    let message = reply.message().await?;
+    storage
+        .staff_approvals
+        .set_message(id, message.channel_id.0, message.id.0)?;
+
+    Ok(Response::info(
+        "Renaming staff needs a second renamer's approval; asked for it above.",
+    ))
+}
+
+/// Handles a press of a staff approval button, which may come long after the rename was
+/// asked for, even across restarts
+pub(super) async fn review_staff_approval(
+    storage: &Storage,
+    ctx: &serenity::Context,
+    mci: &serenity::MessageComponentInteraction,
+    id: u64,
+    approve: bool,
+) -> Result<(), Error> {
+    let respond_privately = |msg: &'static str| {
+        mci.create_interaction_response(&ctx.http, move |r| {
+            r.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|d| d.ephemeral(true).content(msg))
+        })
+    };
+
+    let Some(approval) = storage.staff_approvals.get(id)? else {
+        respond_privately("This rename was already reviewed.").await?;
+        return Ok(());
+    };
+    let guild_id = GuildId(approval.guild_id);
+    let is_renamer = match &mci.member {
+        Some(reviewer) => is_renamer(storage, guild_id, reviewer)?,
+        None => false,
+    };
+    if !is_renamer {
+        respond_privately("You need the Renamer role to review staff renames.").await?;
+        return Ok(());
+    }
+    if mci.user.id.0 == approval.actor_id {
+        respond_privately("A different renamer has to review your rename.").await?;
+        return Ok(());
+    }
+    // Another reviewer may have got there first
+    let Some(approval) = storage.staff_approvals.take(id)? else {
+        respond_privately("This rename was already reviewed.").await?;
+        return Ok(());
+    };
+
+    // The approval is already taken, so a member who left closes the request too
+    let outcome = match guild_id.member(ctx, approval.target_id).await {
+        Err(_) => format!(
+            "{} left the server before the rename to {} was reviewed.",
+            UserId(approval.target_id).mention(),
+            approval.nickname
+        ),
+        Ok(_) if approval.expires_at <= unix_now() => {
+            "This rename request expired before it was approved.".to_string()
+        }
+        Ok(target_member) if approve => match guild_id.member(ctx, approval.actor_id).await {
+            Err(_) => format!(
+                "{} left the server before their rename of {} was approved.",
+                UserId(approval.actor_id).mention(),
+                identify_member(&target_member)
+            ),
+            Ok(actor) => {
+                let response = set_nickname(
+                    storage,
+                    ctx,
+                    guild_id,
+                    &actor,
+                    &target_member,
+                    &approval.nickname,
+                )
+                .await?;
+                format!("{}\nApproved by {}.", response.content, mci.user.mention())
+            }
+        },
+        Ok(target_member) => format!(
+            "{} denied setting the nickname of {} to {}.",
+            mci.user.mention(),
+            identify_member(&target_member),
+            approval.nickname
+        ),
+    };
+
+    mci.create_interaction_response(&ctx.http, |r| {
+        r.kind(InteractionResponseType::UpdateMessage)
+            .interaction_response_data(|d| d.content(outcome).components(|c| c))
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// The member renamed in a history entry, if the entry still exists and the member is
+/// still in the server
+async fn renamed_member(
+    storage: &Storage,
+    ctx: &serenity::Context,
+    guild_id: GuildId,
+    history_id: u64,
+) -> Result<Option<(HistoryEntry, Member)>, Error> {
+    let Some(entry) = storage.history.get(&guild_id, history_id)? else {
+        return Ok(None);
+    };
+    match guild_id.member(ctx, entry.user_id).await {
+        Ok(member) => Ok(Some((entry, member))),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Checks that whoever pressed a rename announcement's button may rename the member it
+/// is about from there, explaining why not if they can't
+pub(super) async fn check_follow_up(
+    storage: &Storage,
+    ctx: &serenity::Context,
+    presser: Option<&Member>,
+    target_member: &Member,
+) -> Result<Option<Response>, Error> {
+    let Some(presser) = presser else {
+        return Ok(Some(Response::error("This only works in a server.")));
+    };
+    // Buttons skip the framework's check for commands an admin disabled
+    if storage
+        .disabled_commands
+        .contains(&target_member.guild_id, "rename")?
+    {
+        return Ok(Some(Response::error(
+            "An admin has disabled renaming in this server.",
+        )));
+    }
+    if !is_renamer(storage, target_member.guild_id, presser)? {
+        return Ok(Some(Response::error(
+            "You need the Renamer role to rename members.",
+        )));
+    }
+    // Buttons can't start a staff approval, which needs a command to reply to
+    if is_staff(storage, target_member)? && presser.user.id != target_member.user.id {
+        return Ok(Some(Response::error(
+            "Renaming staff needs a second renamer's approval; use /rename instead.",
+        )));
+    }
+    Ok(None)
+}
+
+pub(super) async fn ask_rename_again(
+    storage: &Storage,
+    ctx: &serenity::Context,
+    mci: &serenity::MessageComponentInteraction,
+    history_id: u64,
+) -> Result<(), Error> {
+    let guild_id = mci.guild_id.ok_or::<Error>("Not in a server".into())?;
+    let Some((_, target_member)) = renamed_member(storage, ctx, guild_id, history_id).await? else {
+        respond_privately(ctx, mci, Response::error("That member is no longer here.")).await?;
+        return Ok(());
+    };
+    if let Some(problem) =
+        check_follow_up(storage, ctx, mci.member.as_ref(), &target_member).await?
+    {
+        respond_privately(ctx, mci, problem).await?;
+        return Ok(());
+    }
+
+    mci.create_interaction_response(&ctx.http, |r| {
+        r.kind(InteractionResponseType::Modal)
+            .interaction_response_data(|d| {
+                d.custom_id(ComponentAction::RenameAgain(history_id).custom_id())
+                    .title(format!("Rename {}", display_name(&target_member)))
+                    .components(|c| {
+                        c.create_action_row(|r| {
+                            r.create_input_text(|t| {
+                                t.custom_id("nickname")
+                                    .label("New nickname")
+                                    .style(serenity::InputTextStyle::Short)
+                                    .max_length(32)
+                                    .required(true)
+                            })
+                        })
+                    })
+            })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Renames a member with the nickname entered after pressing Rename again
+pub(super) async fn rename_again(
+    storage: &Storage,
+    ctx: &serenity::Context,
+    msi: &serenity::ModalSubmitInteraction,
+    history_id: u64,
+) -> Result<(), Error> {
+    let guild_id = msi.guild_id.ok_or::<Error>("Not in a server".into())?;
+    let nickname = msi
+        .data
+        .components
+        .iter()
+        .flat_map(|row| &row.components)
+        .find_map(|component| match component {
+            serenity::ActionRowComponent::InputText(input) => Some(input.value.clone()),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    let response = match renamed_member(storage, ctx, guild_id, history_id).await? {
+        None => Response::error("That member is no longer here."),
+        Some((_, target_member)) => {
+            match check_follow_up(storage, ctx, msi.member.as_ref(), &target_member).await? {
+                Some(problem) => problem,
+                None => {
+                    let actor = msi.member.as_ref().unwrap();
+                    let nickname = if storage.settings.get_flag(AutoTransliterate, &guild_id)? {
+                        transform::transliterate(&nickname)
+                    } else {
+                        nickname
+                    };
+                    let report = validate_nickname(storage, &guild_id, &nickname)?;
+                    match report.rejection() {
+                        Some(reason) => Response::error(format!(
+                            "{} is not a valid nickname: {}.",
+                            nickname, reason
+                        )),
+                        None => with_warnings(
+                            set_nickname(
+                                storage,
+                                ctx,
+                                guild_id,
+                                actor,
+                                &target_member,
+                                &report.nickname,
+                            )
+                            .await?,
+                            &report,
+                        ),
+                    }
+                }
+            }
+        }
+    };
+
+    msi.create_interaction_response(&ctx.http, |r| {
+        r.kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|d| response.build_interaction(d))
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Puts back the nickname a rename replaced, if nobody has renamed the member since
+pub(super) async fn undo_rename(
+    storage: &Storage,
+    ctx: &serenity::Context,
+    mci: &serenity::MessageComponentInteraction,
+    history_id: u64,
+) -> Result<(), Error> {
+    let guild_id = mci.guild_id.ok_or::<Error>("Not in a server".into())?;
+    let Some((entry, target_member)) = renamed_member(storage, ctx, guild_id, history_id).await?
+    else {
+        respond_privately(ctx, mci, Response::error("That member is no longer here.")).await?;
+        return Ok(());
+    };
+    if let Some(problem) =
+        check_follow_up(storage, ctx, mci.member.as_ref(), &target_member).await?
+    {
+        respond_privately(ctx, mci, problem).await?;
+        return Ok(());
+    }
+    if target_member.nick != entry.nickname {
+        respond_privately(
+            ctx,
+            mci,
+            Response::error(format!(
+                "{} has been renamed since, so this rename can't be undone.",
+                identify_member(&target_member)
+            )),
+        )
+        .await?;
+        return Ok(());
+    }
+    let Some(previous_nickname) = &entry.previous_nickname else {
+        respond_privately(
+            ctx,
+            mci,
+            Response::error(format!(
+                "{} had no nickname before; remove it in Discord to undo this rename.",
+                identify_member(&target_member)
+            )),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let actor = mci.member.as_ref().unwrap();
+    let response = set_nickname(
+        storage,
+        ctx,
+        guild_id,
+        actor,
+        &target_member,
+        previous_nickname,
+    )
+    .await?;
+    mci.create_interaction_response(&ctx.http, |r| {
+        r.kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|d| response.build_interaction(d))
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Why the guild's minimum account or membership age stops the member from being renamed,
+/// if it does. New accounts and members are often raid bots, which moderators handle
+/// differently.
+fn age_gate(
+    storage: &Storage,
+    guild_id: GuildId,
+    member: &Member,
+) -> Result<Option<String>, Error> {
+    let min_hours = |setting| -> Result<u64, Error> {
+        Ok(storage
+            .settings
+            .get(setting, &guild_id)?
+            .and_then(|hours| hours.parse().ok())
+            .unwrap_or(0))
+    };
+    let younger_than = |since: i64, hours: u64| {
+        hours > 0 && unix_now().saturating_sub(since.max(0) as u64) < hours * 60 * 60
+    };
+
+    let created_at = member.user.created_at().unix_timestamp();
+    let account_hours = min_hours(MinAccountAgeHours)?;
+    if younger_than(created_at, account_hours) {
+        return Ok(Some(format!(
+            "The account of {} was created <t:{}:R>; this server doesn't rename accounts younger than {} hour(s).",
+            identify_member(member),
+            created_at,
+            account_hours
+        )));
+    }
+
+    if let Some(joined_at) = member.joined_at.map(|joined_at| joined_at.unix_timestamp()) {
+        let member_hours = min_hours(MinMemberAgeHours)?;
+        if younger_than(joined_at, member_hours) {
+            return Ok(Some(format!(
+                "{} joined <t:{}:R>; this server doesn't rename members who joined less than {} hour(s) ago.",
+                identify_member(member),
+                joined_at,
+                member_hours
+            )));
+        }
+    }
+
+    Ok(None)
+}
+
+pub(super) async fn set_nickname(
+    storage: &Storage,
+    ctx: &serenity::Context,
+    guild_id: GuildId,
+    actor: &Member,
+    target_member: &Member,
+    nickname: &str,
+) -> Result<Response, Error> {
+    if storage.optouts.contains(&target_member.user.id)? {
+        return Ok(Response::info(format!(
+            "{} has opted out of being renamed by renamer.",
+            identify_member(target_member)
+        )));
+    }
+
+    // Members may always rename themselves; otherwise each of the actor's roles may
+    // forbid renaming members of certain other roles
+    if actor.user.id != target_member.user.id {
+        for actor_role_id in &actor.roles {
+            let protected_role_ids = storage.target_rules.get(&guild_id, actor_role_id)?;
+            if let Some(protected_role_id) = protected_role_ids
+                .iter()
+                .find(|role_id| target_member.roles.contains(*role_id))
+            {
+                return Ok(Response::error(format!(
+                    "Members with the {} role can't rename members with the {} role.",
+                    actor_role_id.mention(),
+                    protected_role_id.mention()
+                )));
+            }
+        }
+    }
+
+    if let Some(problem) = age_gate(storage, guild_id, target_member)? {
+        return Ok(Response::error(problem));
+    }
+
+    if let Some(guild) = guild_id.to_guild_cached(ctx) {
+        if let Some(problem) = rename_blocker(&guild, ctx.cache.current_user_id(), target_member) {
+            return Ok(Response::error(problem));
+        }
+    }
+
+    // Every check has passed, so this is what the rename would do
+    if storage.settings.get_flag(ObserveMode, &guild_id)? {
+        info!(
+            "Observe mode: {} would have set the nickname of {} in guild {} to {}",
+            actor.user.id, target_member.user.id, guild_id, nickname
+        );
+        return Ok(Response::info(format!(
+            "Observe mode is on, so nothing changed; otherwise {} would have set the nickname of {} to {}.",
+            describe_member(actor),
+            identify_member(target_member),
+            nickname
+        )));
+    }
+
+    // The member update Discord sends can arrive before apply_nickname records the rename
+    storage
+        .pending_renames
+        .insert(&guild_id, &target_member.user.id, &actor.user.id, nickname)?;
+    let result = apply_nickname(storage, ctx, guild_id, actor, target_member, nickname).await;
+    storage
+        .pending_renames
+        .remove(&guild_id, &target_member.user.id)?;
+    result
+}
+
+/// Asks Discord to change the nickname and records it in history if it did
+async fn apply_nickname(
+    storage: &Storage,
+    ctx: &serenity::Context,
+    guild_id: GuildId,
+    actor: &Member,
+    target_member: &Member,
+    nickname: &str,
+) -> Result<Response, Error> {
+    let http = &ctx.http;
+    let mut edited_member = match target_member.edit(http, |u| u.nickname(nickname)).await {
+        Ok(member) => member,
+        // Discord's error doesn't say why, so point at the likely cause
+        Err(e) if target_member.pending => {
+            return Ok(Response::error(format!(
+                "Discord refused to rename {}, who hasn't completed this server's membership screening yet: {}",
+                identify_member(target_member),
+                e
+            )))
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    // Discord silently ignores some edits, so don't trust the request
+    // alone; confirm against a fresh copy before claiming success
+    if !nickname_applied(&edited_member, nickname) {
+        edited_member = http.get_member(guild_id.0, target_member.user.id.0).await?;
+    }
+
+    let response = if nickname_applied(&edited_member, nickname) {
+        let history_id = storage.record_rename(HistoryEntry {
+            id: 0,
+            guild_id: guild_id.0,
+            user_id: target_member.user.id.0,
+            actor_id: Some(actor.user.id.0),
+            previous_nickname: target_member.nick.clone(),
+            nickname: edited_member.nick.clone(),
+            timestamp: unix_now(),
+            kind: HistoryKind::Rename,
+        })?;
+        Response::success(format!(
+            "{} set the nickname of {} to {}.",
+            describe_member(actor),
+            identify_member(target_member),
+            nickname
+        ))
+        .public()
+        .history_id(history_id)
+    } else {
+        Response::error(format!(
+            "Discord did not apply the nickname change; the nickname of {} is still {}.",
+            identify_member(target_member),
+            edited_member.nick.as_deref().unwrap_or("unset")
+        ))
+    };
+
+    Ok(response)
+}
+
+#[poise::command(
+    slash_command,
+    required_bot_permissions = "MANAGE_NICKNAMES",
+    category = "Renamer"
+)]
+pub(crate) async fn rename(
+    ctx: Context<'_>,
+    username: String,
+    nickname: String,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let mut member_cow = ctx.author_member().await.ok_or::<Error>("foo".into())?;
+    let member = member_cow.to_mut();
+    let guild_id = ctx.guild_id().unwrap();
+    let http = ctx.http();
+
+    if let Some(renamer_role_id) = check_set_up(&ctx, Renamer).await? {
+        let is_renamer = member
+            .user
+            .has_role(http, guild_id, renamer_role_id)
+            .await?;
+        let response = if is_renamer || has_booster_perk(&ctx, member, BoosterPerk::SelfRename)? {
+            let nickname = if storage.settings.get_flag(AutoTransliterate, &guild_id)? {
+                transform::transliterate(&nickname)
+            } else {
+                nickname
+            };
+            let report = validate_nickname(storage, &guild_id, &nickname)?;
+            if let Some(reason) = report.rejection() {
+                Response::error(format!("{} is not a valid nickname: {}.", nickname, reason))
+            } else {
+                let nickname = &report.nickname;
+
+                // Get target user
+                let lookup_mode = LookupMode::for_guild(storage, &guild_id)?;
+                let target_members_vec = lookup_members(&ctx, &username, lookup_mode).await?;
+
+                match target_members_vec.len() {
+                    0 => {
+                        Response::error(format!("Search for '{}' found no users.", username))
+                    }
+                    1 if !is_renamer && target_members_vec[0].user.id != member.user.id => {
+                        Response::error("Server boosters can only rename themselves.")
+                    }
+                    1 => {
+                        let target_member = target_members_vec.first().unwrap();
+                        let threshold = storage.settings
+                            .get(MatchThreshold, &guild_id)?
+                            .and_then(|n| n.parse().ok())
+                            .unwrap_or(DEFAULT_MATCH_THRESHOLD);
+
+                        // Prefix and fuzzy search can land a typo on exactly one (wrong)
+                        // member; make the author confirm anything but a close match
+                        let prompt = format!("Did you mean {}?", identify_member(target_member));
+                        if !lookup_mode.is_exact()
+                            && match_similarity(&username, target_member) * 100.0 < threshold
+                            && !confirm(&ctx, &prompt).await?
+                        {
+                            return Ok(());
+                        }
+                        let response = rename_member(&ctx, member, target_member, nickname).await?;
+                        with_warnings(response, &report)
+                    }
+                    _ => {
+                        Response::error(format!("Search for '{}' found too many users. Specify exactly one user for `username`.", username))
+                    }
+                }
+            }
+        } else {
+            Response::error("You do not have permission to use this command.")
+        };
+        response.send(ctx).await?;
+    }
+
+    Ok(())
+}
+
+/// How long renamers have to approve or deny a nickname request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Request a new nickname for yourself
+#[poise::command(
+    slash_command,
+    required_bot_permissions = "MANAGE_NICKNAMES",
+    category = "Member"
+)]
+pub(super) async fn request(
+    ctx: Context<'_>,
+    #[description = "Nickname you would like"] nickname: String,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let requester = ctx
+        .author_member()
+        .await
+        .ok_or::<Error>("foo".into())?
+        .into_owned();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let nickname = if storage.settings.get_flag(AutoTransliterate, &guild_id)? {
+        transform::transliterate(&nickname)
+    } else {
+        nickname
+    };
+    let report = validate_nickname(storage, &guild_id, &nickname)?;
+    if let Some(reason) = report.rejection() {
+        Response::error(format!("{} is not a valid nickname: {}.", nickname, reason))
+            .send(ctx)
+            .await?;
+        return Ok(());
+    }
+    let nickname = report.nickname.clone();
+
+    if storage.settings.get_flag(SelfService, &guild_id)? {
+        let response = set_nickname(
+            storage,
+            ctx.serenity_context(),
+            guild_id,
+            &requester,
+            &requester,
+            &nickname,
+        )
+        .await?;
+        with_warnings(response, &report).send(ctx).await?;
+        return Ok(());
+    }
+
+    if check_set_up(&ctx, Renamer).await?.is_none() {
+        return Ok(());
+    }
+
+    let id = storage.staff_approvals.insert(&PendingApproval {
+        guild_id: guild_id.0,
+        actor_id: requester.user.id.0,
+        target_id: requester.user.id.0,
+        nickname: nickname.clone(),
+        expires_at: unix_now() + REQUEST_TIMEOUT.as_secs(),
+        message: None,
+        kind: ApprovalKind::NicknameRequest,
+    })?;
+    let reply = ctx
+        .send(|m| {
+            m.content(format!(
+                "{} requests the nickname {}. Anyone with the Renamer role can review this.",
+                identify_member(&requester),
+                nickname
+            ))
+            .components(|c| {
+                c.create_action_row(|r| {
+                    r.create_button(|b| {
+                        b.custom_id(
+                            ComponentAction::ReviewNicknameRequest { id, approve: true }
+                                .custom_id(),
+                        )
+                        .label("Approve")
+                        .style(ButtonStyle::Success)
+                    })
+                    .create_button(|b| {
+                        b.custom_id(
+                            ComponentAction::ReviewNicknameRequest { id, approve: false }
+                                .custom_id(),
+                        )
+                        .label("Deny")
+                        .style(ButtonStyle::Danger)
+                    })
+                })
+            })
+        })
+        .await?;
+    let message = reply.message().await?;
+    storage
+        .staff_approvals
+        .set_message(id, message.channel_id.0, message.id.0)?;
+
+    Ok(())
+}
+
+/// Handles a press of a nickname request's buttons, which may come long after the
+/// request was made, even across restarts
+pub(super) async fn review_nickname_request(
+    storage: &Storage,
+    ctx: &serenity::Context,
+    mci: &serenity::MessageComponentInteraction,
+    id: u64,
+    approve: bool,
+) -> Result<(), Error> {
+    let Some(request) = storage.staff_approvals.get(id)? else {
+        respond_privately(
+            ctx,
+            mci,
+            Response::error("This request was already reviewed."),
+        )
+        .await?;
+        return Ok(());
+    };
+    let guild_id = GuildId(request.guild_id);
+    let reviewer = match &mci.member {
+        Some(reviewer) if is_renamer(storage, guild_id, reviewer)? => reviewer,
+        _ => {
+            respond_privately(
+                ctx,
+                mci,
+                Response::error("You need the Renamer role to review nickname requests."),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    // Another reviewer may have got there first
+    let Some(request) = storage.staff_approvals.take(id)? else {
+        respond_privately(
+            ctx,
+            mci,
+            Response::error("This request was already reviewed."),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let outcome = match guild_id.member(ctx, request.target_id).await {
+        Err(_) => format!(
+            "{} left the server before their request for the nickname {} was reviewed.",
+            UserId(request.target_id).mention(),
+            request.nickname
+        ),
+        Ok(_) if request.expires_at <= unix_now() => format!(
+            "The request by {} for the nickname {} expired before it was reviewed.",
+            UserId(request.target_id).mention(),
+            request.nickname
+        ),
+        Ok(requester) if approve => {
+            set_nickname(
+                storage,
+                ctx,
+                guild_id,
+                reviewer,
+                &requester,
+                &request.nickname,
+            )
+            .await?
+            .content
+        }
+        Ok(requester) => format!(
+            "{} denied the request by {} for the nickname {}.",
+            describe_member(reviewer),
+            identify_member(&requester),
+            request.nickname
+        ),
+    };
+
+    mci.create_interaction_response(&ctx.http, |r| {
+        r.kind(InteractionResponseType::UpdateMessage)
+            .interaction_response_data(|d| d.content(outcome).components(|c| c))
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Show how close the app is to Discord's rate limits in this server
+#[poise::command(
+    slash_command,
+    required_permissions = "MANAGE_NICKNAMES",
+    category = "Moderator"
+)]
+pub(super) async fn status(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let routes = ctx.http().ratelimiter.routes();
+    let routes = routes.read().await;
+
+    let mut lines = Vec::new();
+    for (name, route) in [
+        ("Nickname edits", Route::GuildsIdMembersId(guild_id.0)),
+        ("Role changes", Route::GuildsIdMembersIdRolesId(guild_id.0)),
+    ] {
+        lines.push(match routes.get(&route) {
+            Some(ratelimit) => {
+                let ratelimit = ratelimit.lock().await;
+                format!(
+                    "{}: {}/{} requests left, resetting in {}s",
+                    name,
+                    ratelimit.remaining(),
+                    ratelimit.limit(),
+                    ratelimit.reset_after().unwrap_or_default().as_secs()
+                )
+            }
+            None => format!("{}: no requests since the app started", name),
+        });
+    }
+    Response::info(lines.join("\n")).send(ctx).await?;
+
+    Ok(())
+}
+
+/// Replace non-Latin characters in a member's nickname with a Latin approximation
+#[poise::command(
+    slash_command,
+    required_bot_permissions = "MANAGE_NICKNAMES",
+    category = "Renamer"
+)]
+pub(super) async fn transliterate(
+    ctx: Context<'_>,
+    #[description = "Member whose nickname to transliterate"] member: Member,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let mut author_cow = ctx.author_member().await.ok_or::<Error>("foo".into())?;
+    let author = author_cow.to_mut();
+    let guild_id = ctx.guild_id().unwrap();
+    let http = ctx.http();
+
+    if let Some(renamer_role_id) = check_set_up(&ctx, Renamer).await? {
+        let response = if author
+            .user
+            .has_role(http, guild_id, renamer_role_id)
+            .await?
+            || member.user.id == author.user.id
+                && has_booster_perk(&ctx, author, BoosterPerk::Styling)?
+        {
+            let current_name = display_name(&member);
+            let nickname = transform::transliterate(current_name);
+
+            if nickname == current_name {
+                Response::info(format!(
+                    "{} has no characters to transliterate.",
+                    current_name
+                ))
+            } else {
+                let report = validate_nickname(storage, &guild_id, &nickname)?;
+                match report.rejection() {
+                    Some(reason) => Response::error(format!(
+                        "{} transliterates to an invalid nickname: {}.",
+                        current_name, reason
+                    )),
+                    None => {
+                        let response =
+                            rename_member(&ctx, author, &member, &report.nickname).await?;
+                        with_warnings(response, &report)
+                    }
+                }
+            }
+        } else {
+            Response::error("You do not have permission to use this command.")
+        };
+        response.send(ctx).await?;
+    }
+
+    Ok(())
+}
+
+/// Give a member a random pronounceable nickname
+#[poise::command(
+    slash_command,
+    required_bot_permissions = "MANAGE_NICKNAMES",
+    category = "Renamer"
+)]
+pub(super) async fn random(
+    ctx: Context<'_>,
+    #[description = "Member to give a random nickname"] member: Member,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let mut author_cow = ctx.author_member().await.ok_or::<Error>("foo".into())?;
+    let author = author_cow.to_mut();
+    let guild_id = ctx.guild_id().unwrap();
+    let http = ctx.http();
+
+    if let Some(renamer_role_id) = check_set_up(&ctx, Renamer).await? {
+        let response = if author
+            .user
+            .has_role(http, guild_id, renamer_role_id)
+            .await?
+            || member.user.id == author.user.id
+                && has_booster_perk(&ctx, author, BoosterPerk::Styling)?
+        {
+            let seed_words: Vec<String> = storage
+                .settings
+                .get(RandomSeedWords, &guild_id)?
+                .map(|words| words.split(',').map(|w| w.trim().to_string()).collect())
+                .unwrap_or_default();
+            let min_syllables = storage
+                .settings
+                .get(RandomMinSyllables, &guild_id)?
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(random_name::DEFAULT_MIN_SYLLABLES);
+            let max_syllables = storage
+                .settings
+                .get(RandomMaxSyllables, &guild_id)?
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(random_name::DEFAULT_MAX_SYLLABLES);
+
+            let nickname =
+                random_name::pronounceable_name(&seed_words, min_syllables, max_syllables);
+            let report = validate_nickname(storage, &guild_id, &nickname)?;
+            match report.rejection() {
+                Some(reason) => Response::error(format!(
+                    "The random nickname {} is invalid: {}.",
+                    nickname, reason
+                )),
+                None => {
+                    let response = rename_member(&ctx, author, &member, &report.nickname).await?;
+                    with_warnings(response, &report)
+                }
+            }
+        } else {
+            Response::error("You do not have permission to use this command.")
+        };
+        response.send(ctx).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,355 @@
+//! Periodic sweeps that check every nickname in a guild against its validation pipeline,
+//! catching nicknames set outside the app
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::TryStreamExt;
+use poise::serenity_prelude as serenity;
+use poise::serenity_prelude::{
+    ButtonStyle, ChannelId, GatewayIntents, GuildId, Member, Mentionable, UserId,
+};
+use tokio::time::Instant;
+use tracing::{error, warn};
+
+use super::rename::{check_follow_up, set_nickname};
+use super::{
+    check_members_intent, identify_member, is_staff, respond_privately, ComponentAction, Context,
+    Error,
+};
+use crate::response::Response;
+use crate::services::storage::Setting::*;
+use crate::services::storage::{unix_now, Storage};
+use crate::services::validation::{load_pipeline, Outcome, Pipeline};
+
+/// How often guilds with sweeps turned on are swept
+const SWEEP_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How often to look for guilds whose sweep is due. Each guild's last sweep is stored, so
+/// restarting the app doesn't put off sweeps.
+const SWEEP_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Most violations listed in a sweep report, keeping it within Discord's message length
+/// limit
+const SWEEP_REPORT_LIMIT: usize = 10;
+
+/// A nickname that breaks the guild's validation rules
+struct Violation {
+    member: Member,
+    problems: Vec<String>,
+    /// The nickname with the offending parts stripped, if that leaves one the rules accept
+    fix: Option<String>,
+}
+
+/// Checks a member's nickname, returning what's wrong with it if anything is
+fn check_member(pipeline: &Pipeline, member: &Member) -> Option<Violation> {
+    let nickname = member.nick.as_deref()?;
+    let report = pipeline.validate(nickname);
+    let problems: Vec<String> = report
+        .results
+        .iter()
+        .filter_map(|(_, outcome)| match outcome {
+            Outcome::Warned(problem) | Outcome::Stripped(problem) | Outcome::Rejected(problem) => {
+                Some(problem.clone())
+            }
+            Outcome::Off | Outcome::Passed => None,
+        })
+        .collect();
+    if problems.is_empty() {
+        return None;
+    }
+    let fix = (report.rejection().is_none() && report.nickname != nickname)
+        .then(|| report.nickname.clone());
+    Some(Violation {
+        member: member.clone(),
+        problems,
+        fix,
+    })
+}
+
+fn sweep_channel(storage: &Storage, guild_id: &GuildId) -> Result<Option<ChannelId>, Error> {
+    Ok(storage
+        .settings
+        .get(SweepChannel, guild_id)?
+        .and_then(|id| id.parse().ok())
+        .map(ChannelId))
+}
+
+/// Sweeps every guild that turned sweeps on, once per [`SWEEP_INTERVAL`]
+pub(crate) async fn sweep_nicknames(
+    ctx: serenity::Context,
+    storage: Arc<Storage>,
+    missing_intents: GatewayIntents,
+) {
+    if missing_intents.contains(GatewayIntents::GUILD_MEMBERS) {
+        warn!("Not sweeping nicknames, which needs the Server Members intent");
+        return;
+    }
+    // The first check waits for the gateway to send the guilds
+    let mut interval =
+        tokio::time::interval_at(Instant::now() + SWEEP_CHECK_INTERVAL, SWEEP_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        for guild_id in ctx.cache.guilds() {
+            let channel_id = match due_sweep_channel(&storage, &guild_id) {
+                Ok(Some(channel_id)) => channel_id,
+                Ok(None) => continue,
+                Err(e) => {
+                    error!("Failed to read sweep settings of guild {}: {}", guild_id, e);
+                    continue;
+                }
+            };
+            // Recorded up front so a guild whose sweep fails isn't retried every hour
+            let recorded =
+                storage
+                    .settings
+                    .set_state(LastSweep, &guild_id, &unix_now().to_string());
+            if let Err(e) = recorded {
+                error!("Failed to record sweep of guild {}: {}", guild_id, e);
+                continue;
+            }
+            if let Err(e) = sweep_guild(&storage, &ctx, guild_id, channel_id).await {
+                error!("Failed to sweep nicknames in guild {}: {}", guild_id, e);
+            }
+        }
+    }
+}
+
+/// The guild's report channel if it turned sweeps on and its last sweep was at least
+/// [`SWEEP_INTERVAL`] ago
+fn due_sweep_channel(storage: &Storage, guild_id: &GuildId) -> Result<Option<ChannelId>, Error> {
+    let last_sweep: u64 = storage
+        .settings
+        .get(LastSweep, guild_id)?
+        .and_then(|time| time.parse().ok())
+        .unwrap_or(0);
+    if last_sweep + SWEEP_INTERVAL.as_secs() > unix_now() {
+        return Ok(None);
+    }
+    sweep_channel(storage, guild_id)
+}
+
+/// Checks every nickname in the guild, fixes what it can if the guild asked for that and
+/// posts a report with a button to fix each remaining nickname that can be fixed.
+/// Returns how many nicknames broke the rules.
+async fn sweep_guild(
+    storage: &Storage,
+    ctx: &serenity::Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> Result<usize, Error> {
+    let pipeline = load_pipeline(storage, &guild_id)?;
+    let members: Vec<Member> = guild_id.members_iter(&ctx.http).try_collect().await?;
+    let violations: Vec<Violation> = members
+        .iter()
+        .filter(|member| !member.user.bot)
+        .filter_map(|member| check_member(&pipeline, member))
+        .collect();
+    let total = violations.len();
+
+    let mut remaining = Vec::new();
+    let mut fixed = 0;
+    if storage.settings.get_flag(SweepAutoFix, &guild_id)? {
+        let bot = guild_id.member(ctx, ctx.cache.current_user_id()).await?;
+        for violation in violations {
+            // Staff renames need a second renamer's approval, which a sweep can't give
+            let fix = match &violation.fix {
+                Some(fix) if !is_staff(storage, &violation.member)? => fix.clone(),
+                _ => {
+                    remaining.push(violation);
+                    continue;
+                }
+            };
+            // One failed edit shouldn't lose the report of everything else
+            match set_nickname(storage, ctx, guild_id, &bot, &violation.member, &fix).await {
+                Ok(response) if response.is_success() => fixed += 1,
+                Ok(_) => remaining.push(violation),
+                Err(e) => {
+                    error!(
+                        "Failed to fix the nickname of {} in guild {}: {}",
+                        violation.member.user.id, guild_id, e
+                    );
+                    remaining.push(violation);
+                }
+            }
+        }
+    } else {
+        remaining = violations;
+    }
+
+    if total == 0 {
+        return Ok(0);
+    }
+
+    let mut lines = Vec::new();
+    if fixed > 0 {
+        lines.push(format!(
+            "Fixed {} of them by stripping the offending parts.",
+            fixed
+        ));
+    }
+    for violation in remaining.iter().take(SWEEP_REPORT_LIMIT) {
+        lines.push(format!(
+            "{} as {}: {}",
+            identify_member(&violation.member),
+            violation.member.nick.as_deref().unwrap_or_default(),
+            violation.problems.join("; ")
+        ));
+    }
+    if remaining.len() > SWEEP_REPORT_LIMIT {
+        lines.push(format!(
+            "...and {} more.",
+            remaining.len() - SWEEP_REPORT_LIMIT
+        ));
+    }
+
+    let fixable: Vec<&Violation> = remaining
+        .iter()
+        .take(SWEEP_REPORT_LIMIT)
+        .filter(|violation| violation.fix.is_some())
+        .collect();
+    channel_id
+        .send_message(&ctx.http, |m| {
+            m.content(format!(
+                "Nickname sweep found {} nickname(s) breaking this server's rules.\n{}",
+                total,
+                lines.join("\n")
+            ))
+            .components(|c| {
+                for row in fixable.chunks(5) {
+                    c.create_action_row(|r| {
+                        for violation in row {
+                            r.create_button(|b| {
+                                b.custom_id(
+                                    ComponentAction::FixNickname(violation.member.user.id.0)
+                                        .custom_id(),
+                                )
+                                .label(format!("Fix {}", violation.member.user.name))
+                                .style(ButtonStyle::Secondary)
+                            });
+                        }
+                        r
+                    });
+                }
+                c
+            })
+        })
+        .await?;
+
+    Ok(total)
+}
+
+/// Fixes a nickname listed in a sweep report, if it still breaks the rules
+pub(super) async fn fix_nickname(
+    storage: &Storage,
+    ctx: &serenity::Context,
+    mci: &serenity::MessageComponentInteraction,
+    user_id: UserId,
+) -> Result<(), Error> {
+    let Some(guild_id) = mci.guild_id else {
+        return Ok(());
+    };
+    let Ok(member) = guild_id.member(ctx, user_id).await else {
+        respond_privately(
+            ctx,
+            mci,
+            Response::error("That member is no longer in the server."),
+        )
+        .await?;
+        return Ok(());
+    };
+    if let Some(problem) = check_follow_up(storage, ctx, mci.member.as_ref(), &member).await? {
+        respond_privately(ctx, mci, problem).await?;
+        return Ok(());
+    }
+
+    let pipeline = load_pipeline(storage, &guild_id)?;
+    let response = match check_member(&pipeline, &member) {
+        None => Response::info(format!(
+            "The nickname of {} no longer breaks any rules.",
+            identify_member(&member)
+        )),
+        Some(Violation { fix: None, .. }) => Response::info(format!(
+            "The nickname of {} can't be fixed by stripping it; rename them with /rename.",
+            identify_member(&member)
+        )),
+        Some(Violation { fix: Some(fix), .. }) => {
+            let actor = mci.member.as_ref().unwrap();
+            set_nickname(storage, ctx, guild_id, actor, &member, &fix).await?
+        }
+    };
+    respond_privately(ctx, mci, response).await?;
+
+    Ok(())
+}
+
+#[poise::command(slash_command, subcommands("sweep_configure", "sweep_run"))]
+pub(super) async fn sweep(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Check every nickname against the validation rules once a day
+#[poise::command(slash_command, rename = "configure", category = "Admin")]
+async fn sweep_configure(
+    ctx: Context<'_>,
+    #[description = "Channel to post sweep reports in; leave out to stop sweeping nicknames"]
+    channel: Option<serenity::GuildChannel>,
+    #[description = "Strip offending parts from nicknames without waiting for a renamer"]
+    auto_fix: Option<bool>,
+) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    let auto_fix = auto_fix.unwrap_or(false);
+    if channel.is_some() && !check_members_intent(&ctx).await? {
+        return Ok(());
+    }
+
+    let channel_id = channel.as_ref().map(|channel| channel.id.to_string());
+    storage.set_setting(SweepChannel, &guild_id, channel_id.as_deref().unwrap_or(""))?;
+    storage.set_setting(SweepAutoFix, &guild_id, &auto_fix.to_string())?;
+
+    let msg = match channel {
+        Some(channel) if auto_fix => format!(
+            "Nicknames will be checked daily and fixed where stripping can; the rest will be reported in {}.",
+            channel.mention()
+        ),
+        Some(channel) => format!(
+            "Nicknames will be checked daily and reported in {}.",
+            channel.mention()
+        ),
+        None => "Nicknames will no longer be swept.".to_string(),
+    };
+    Response::success(msg).send(ctx).await?;
+
+    Ok(())
+}
+
+/// Sweep nicknames now instead of waiting for the daily sweep
+#[poise::command(slash_command, rename = "run", category = "Admin")]
+async fn sweep_run(ctx: Context<'_>) -> Result<(), Error> {
+    let storage = &ctx.data().storage;
+    let guild_id = ctx.guild_id().unwrap();
+    if !check_members_intent(&ctx).await? {
+        return Ok(());
+    }
+
+    let Some(channel_id) = sweep_channel(storage, &guild_id)? else {
+        Response::error("Choose a channel for reports with /renamer admin sweep configure first.")
+            .send(ctx)
+            .await?;
+        return Ok(());
+    };
+
+    ctx.defer_ephemeral().await?;
+    let total = sweep_guild(storage, ctx.serenity_context(), guild_id, channel_id).await?;
+    let msg = match total {
+        0 => "Every nickname follows this server's rules.".to_string(),
+        total => format!(
+            "Found {} nickname(s) breaking this server's rules; see {}.",
+            total,
+            channel_id.mention()
+        ),
+    };
+    Response::success(msg).send(ctx).await?;
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+//! Production and staging apps run from the same binary, configured from the environment
+//!
+//! `ENVIRONMENT=staging` switches the token, data directory, command prefix and command
+//! registration so a staging app can run alongside production without sharing anything.
+
+use std::env;
+use std::path::PathBuf;
+
+use poise::serenity_prelude::GuildId;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Environment {
+    Production,
+    Staging,
+}
+
+impl Environment {
+    /// Reads `ENVIRONMENT`, which defaults to production
+    pub(crate) fn current() -> Self {
+        match env::var("ENVIRONMENT").as_deref() {
+            Ok("production") | Err(_) => Environment::Production,
+            Ok("staging") => Environment::Staging,
+            Ok(_) => panic!("ENVIRONMENT must be production or staging"),
+        }
+    }
+
+    /// Reads `DISCORD_TOKEN` in production and `STAGING_DISCORD_TOKEN` in staging
+    pub(crate) fn token(&self) -> String {
+        let var = match self {
+            Environment::Production => "DISCORD_TOKEN",
+            Environment::Staging => "STAGING_DISCORD_TOKEN",
+        };
+        env::var(var).unwrap_or_else(|_| panic!("Expected {} in the environment", var))
+    }
+
+    /// Prefix for owner commands, from `COMMAND_PREFIX` if set
+    pub(crate) fn prefix(&self) -> String {
+        env::var("COMMAND_PREFIX").unwrap_or_else(|_| match self {
+            Environment::Production => "~".into(),
+            Environment::Staging => "?".into(),
+        })
+    }
+
+    /// Server to register commands in instead of globally, so staging changes show up
+    /// immediately and only where they're being tested. Read from `STAGING_GUILD_ID`,
+    /// which staging requires.
+    pub(crate) fn test_guild(&self) -> Option<GuildId> {
+        match self {
+            Environment::Production => None,
+            Environment::Staging => Some(GuildId(
+                env::var("STAGING_GUILD_ID")
+                    .ok()
+                    .and_then(|id| id.parse().ok())
+                    .expect("STAGING_GUILD_ID must be set to a server ID in staging"),
+            )),
+        }
+    }
+}
+
+/// Where the databases live: `DATA_DIR` if set, otherwise the working directory in
+/// production and `staging` in staging
+pub(crate) fn data_dir() -> PathBuf {
+    let dir = env::var("DATA_DIR").unwrap_or_else(|_| match Environment::current() {
+        Environment::Production => ".".into(),
+        Environment::Staging => "staging".into(),
+    });
+    PathBuf::from(dir)
+}
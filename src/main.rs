@@ -1,9 +1,66 @@
 mod commands;
+mod environment;
+mod presence;
+mod preset;
+mod random_name;
+mod response;
+mod services;
+mod transform;
 
+use poise::serenity_prelude as serenity;
+use poise::serenity_prelude::http::request::{Request, RequestBuilder};
+use poise::serenity_prelude::http::routing::RouteInfo;
 use poise::serenity_prelude::GatewayIntents;
 use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
 
-use crate::commands::{rename, renamer, Data};
+use crate::commands::{
+    command_enabled, event_handler, expire_staff_approvals, log_events, migrations,
+    notify_watchers, on_error, process_forget_requests, record_invocation, rename, renamer,
+    sweep_nicknames, usage, Data,
+};
+use crate::environment::Environment;
+use crate::presence::{update_presence, PresenceConfig};
+use crate::services::dm_limiter::DmLimiter;
+use crate::services::storage::Storage;
+
+/// Privileged intents the app asks for when they are enabled in the developer portal
+const PRIVILEGED_INTENTS: GatewayIntents =
+    GatewayIntents::GUILD_PRESENCES.union(GatewayIntents::GUILD_MEMBERS);
+
+/// Privileged intents enabled for the app in the developer portal. Asking the gateway for
+/// one that isn't enabled makes the connection fail. If the lookup itself fails, all of
+/// them are assumed to be enabled.
+async fn enabled_privileged_intents(token: &str) -> GatewayIntents {
+    // Application flags for the Presence and Server Members intents, either the full or
+    // the limited (unverified app) version
+    const GATEWAY_PRESENCE: u64 = 1 << 12 | 1 << 13;
+    const GATEWAY_GUILD_MEMBERS: u64 = 1 << 14 | 1 << 15;
+
+    let http = serenity::Http::new(token);
+    let request = Request::new(RequestBuilder::new(RouteInfo::GetCurrentApplicationInfo));
+    let flags = match http.fire::<serde_json::Value>(request).await {
+        Ok(info) => info["flags"].as_u64().unwrap_or(0),
+        Err(e) => {
+            warn!(
+                "Couldn't look up which privileged intents are enabled: {}",
+                e
+            );
+            return PRIVILEGED_INTENTS;
+        }
+    };
+
+    let mut intents = GatewayIntents::empty();
+    if flags & GATEWAY_PRESENCE != 0 {
+        intents |= GatewayIntents::GUILD_PRESENCES;
+    }
+    if flags & GATEWAY_GUILD_MEMBERS != 0 {
+        intents |= GatewayIntents::GUILD_MEMBERS;
+    }
+    intents
+}
 
 #[tokio::main]
 async fn main() {
@@ -17,27 +74,89 @@ async fn main() {
     // `RUST_LOG` to `debug`.
     tracing_subscriber::fmt::init();
 
-    let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
+    let environment = Environment::current();
+    let token = environment.token();
+    let test_guild = environment.test_guild();
 
-    let gateway_intents = GatewayIntents::non_privileged()
-        | GatewayIntents::GUILD_PRESENCES
-        | GatewayIntents::GUILD_MEMBERS;
+    // Users' forget_me requests are carried out after this many hours, giving them a
+    // chance to change their mind. Defaults to deleting immediately.
+    let forget_me_grace_hours: u64 = env::var("FORGET_ME_GRACE_PERIOD_HOURS")
+        .map(|hours| {
+            hours
+                .parse()
+                .expect("FORGET_ME_GRACE_PERIOD_HOURS must be a whole number")
+        })
+        .unwrap_or(0);
+
+    let presence_config = PresenceConfig::from_env();
+
+    let privileged_intents = enabled_privileged_intents(&token).await;
+    let missing_intents = PRIVILEGED_INTENTS - privileged_intents;
+    if missing_intents.contains(GatewayIntents::GUILD_MEMBERS) {
+        warn!(
+            "The Server Members intent is disabled in the developer portal; nickname changes \
+            made outside the app won't be recorded or sent to watchers"
+        );
+    }
+    if missing_intents.contains(GatewayIntents::GUILD_PRESENCES) {
+        warn!("The Presence intent is disabled in the developer portal");
+    }
+    let gateway_intents = GatewayIntents::non_privileged() | privileged_intents;
+
+    let storage = Arc::new(Storage::open().expect("Failed to open the databases"));
 
     let framework = poise::Framework::builder()
         .options(poise::FrameworkOptions {
-            commands: vec![rename(), renamer()],
+            commands: vec![rename(), renamer(), usage(), migrations()],
             prefix_options: poise::PrefixFrameworkOptions {
-                prefix: Some("~".into()),
+                prefix: Some(environment.prefix()),
                 ..Default::default()
             },
+            // Replies mention members so they can be clicked and told apart; don't ping them
+            allowed_mentions: Some({
+                let mut allowed_mentions = serenity::CreateAllowedMentions::default();
+                allowed_mentions.empty_parse();
+                allowed_mentions
+            }),
+            command_check: Some(|ctx| Box::pin(command_enabled(ctx))),
+            pre_command: |ctx| Box::pin(record_invocation(ctx)),
+            on_error: |error| Box::pin(on_error(error)),
+            event_handler: |ctx, event, framework, data| {
+                Box::pin(event_handler(ctx, event, framework, data))
+            },
             ..Default::default()
         })
         .token(token)
         .intents(gateway_intents)
-        .setup(|ctx, _ready, framework| {
+        .setup(move |ctx, _ready, framework| {
             Box::pin(async move {
-                poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                Ok(Data {})
+                let commands = &framework.options().commands;
+                match test_guild {
+                    Some(guild_id) => {
+                        poise::builtins::register_in_guild(ctx, commands, guild_id).await?
+                    }
+                    None => poise::builtins::register_globally(ctx, commands).await?,
+                }
+                tokio::spawn(process_forget_requests(storage.clone()));
+                tokio::spawn(expire_staff_approvals(ctx.clone(), storage.clone()));
+                tokio::spawn(update_presence(ctx.clone(), presence_config));
+                tokio::spawn(sweep_nicknames(
+                    ctx.clone(),
+                    storage.clone(),
+                    missing_intents,
+                ));
+                tokio::spawn(log_events(storage.events.subscribe()));
+                tokio::spawn(notify_watchers(
+                    ctx.clone(),
+                    storage.clone(),
+                    storage.events.subscribe(),
+                ));
+                Ok(Data {
+                    storage,
+                    dm_limiter: DmLimiter::default(),
+                    forget_me_grace_period: Duration::from_secs(forget_me_grace_hours * 3600),
+                    missing_intents,
+                })
             })
         });
 
@@ -0,0 +1,66 @@
+//! The app's activity and online status, configured from the environment
+
+use std::env;
+use std::time::Duration;
+
+use poise::serenity_prelude::{Activity, Context, OnlineStatus};
+
+/// How often the server count in the activity is refreshed
+const UPDATE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+/// How long after connecting the activity is first set; servers are only counted once
+/// the gateway has sent them, which takes a few seconds after the app is ready
+const FIRST_UPDATE_DELAY: Duration = Duration::from_secs(60);
+
+pub(crate) struct PresenceConfig {
+    /// One of playing, listening, watching or competing
+    kind: String,
+    /// Activity text; `{guilds}` is replaced with the number of servers the app is in
+    template: String,
+    status: OnlineStatus,
+}
+
+impl PresenceConfig {
+    /// Reads `BOT_ACTIVITY_TYPE`, `BOT_ACTIVITY` and `BOT_STATUS`
+    pub(crate) fn from_env() -> Self {
+        let kind = env::var("BOT_ACTIVITY_TYPE").unwrap_or_else(|_| "watching".into());
+        if !matches!(
+            kind.as_str(),
+            "playing" | "listening" | "watching" | "competing"
+        ) {
+            panic!("BOT_ACTIVITY_TYPE must be playing, listening, watching or competing");
+        }
+        let status = match env::var("BOT_STATUS").as_deref() {
+            Ok("online") | Err(_) => OnlineStatus::Online,
+            Ok("idle") => OnlineStatus::Idle,
+            Ok("dnd") => OnlineStatus::DoNotDisturb,
+            Ok("invisible") => OnlineStatus::Invisible,
+            Ok(_) => panic!("BOT_STATUS must be online, idle, dnd or invisible"),
+        };
+        Self {
+            kind,
+            template: env::var("BOT_ACTIVITY")
+                .unwrap_or_else(|_| "/renamer help | {guilds} servers".into()),
+            status,
+        }
+    }
+
+    fn activity(&self, guild_count: usize) -> Activity {
+        let text = self.template.replace("{guilds}", &guild_count.to_string());
+        match self.kind.as_str() {
+            "playing" => Activity::playing(text),
+            "listening" => Activity::listening(text),
+            "competing" => Activity::competing(text),
+            _ => Activity::watching(text),
+        }
+    }
+}
+
+pub(crate) async fn update_presence(ctx: Context, config: PresenceConfig) {
+    let start = tokio::time::Instant::now() + FIRST_UPDATE_DELAY;
+    let mut interval = tokio::time::interval_at(start, UPDATE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let activity = config.activity(ctx.cache.guild_count());
+        ctx.set_presence(Some(activity), config.status).await;
+    }
+}
@@ -0,0 +1,134 @@
+//! Shareable bundles of blocklist words, regex rules and random name seed words
+//!
+//! Guilds can export what they have configured as a JSON preset and import presets made
+//! by other guilds, or apply one of the presets built into the app.
+
+use serde::{Deserialize, Serialize};
+
+use crate::services::validation::Pipeline;
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub(crate) struct Preset {
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) blocked_words: Vec<String>,
+    #[serde(default)]
+    pub(crate) regex_rules: Vec<String>,
+    #[serde(default)]
+    pub(crate) seed_words: Vec<String>,
+}
+
+impl Preset {
+    /// Adds the preset's blocked words and regex rules to a pipeline, skipping any it
+    /// already has. Step modes are left alone, so the matching steps may still need
+    /// turning on.
+    pub(crate) fn apply(&self, pipeline: &mut Pipeline) {
+        for word in &self.blocked_words {
+            let word = word.trim().to_lowercase();
+            if !word.is_empty() && !pipeline.blocked_words.contains(&word) {
+                pipeline.blocked_words.push(word);
+            }
+        }
+        for rule in &self.regex_rules {
+            if !pipeline.regex_rules.contains(rule) {
+                pipeline.regex_rules.push(rule.clone());
+            }
+        }
+    }
+
+    /// Whether the preset has anything to apply
+    pub(crate) fn is_empty(&self) -> bool {
+        self.blocked_words.is_empty() && self.regex_rules.is_empty() && self.seed_words.is_empty()
+    }
+
+    /// One-line summary of what the preset contains
+    pub(crate) fn describe(&self) -> String {
+        format!(
+            "**{}**: {} blocked word(s), {} regex rule(s), {} seed word(s)",
+            self.name,
+            self.blocked_words.len(),
+            self.regex_rules.len(),
+            self.seed_words.len()
+        )
+    }
+}
+
+/// Presets shipped with the app
+pub(crate) fn built_in() -> Vec<Preset> {
+    let strings = |items: &[&str]| items.iter().map(|s| s.to_string()).collect();
+    vec![
+        Preset {
+            name: "family-friendly".into(),
+            blocked_words: strings(&[
+                "fuck", "shit", "bitch", "cunt", "dick", "cock", "pussy", "slut", "whore", "porn",
+                "nsfw",
+            ]),
+            ..Default::default()
+        },
+        Preset {
+            name: "no-links".into(),
+            regex_rules: strings(&[r"https?://", r"discord\.gg/", r"\.(com|net|org|gg|io)\b"]),
+            ..Default::default()
+        },
+        Preset {
+            name: "fantasy".into(),
+            seed_words: strings(&[
+                "dragon",
+                "merlin",
+                "elven",
+                "rune",
+                "ember",
+                "thorin",
+                "galadriel",
+                "sylvan",
+            ]),
+            ..Default::default()
+        },
+        Preset {
+            name: "space".into(),
+            seed_words: strings(&[
+                "nebula",
+                "orion",
+                "comet",
+                "pulsar",
+                "andromeda",
+                "nova",
+                "zenith",
+                "cosmo",
+            ]),
+            ..Default::default()
+        },
+    ]
+}
+
+/// Finds a built-in preset by name, ignoring case
+pub(crate) fn find_built_in(name: &str) -> Option<Preset> {
+    built_in()
+        .into_iter()
+        .find(|preset| preset.name.eq_ignore_ascii_case(name.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::validation::case_insensitive;
+
+    #[test]
+    fn built_in_regex_rules_compile() {
+        for preset in built_in() {
+            for rule in &preset.regex_rules {
+                assert!(case_insensitive(rule).is_some(), "{}", rule);
+            }
+        }
+    }
+
+    #[test]
+    fn applying_twice_adds_nothing() {
+        let preset = find_built_in("Family-Friendly").unwrap();
+        let mut pipeline = Pipeline::default();
+        preset.apply(&mut pipeline);
+        let count = pipeline.blocked_words.len();
+        preset.apply(&mut pipeline);
+        assert_eq!(pipeline.blocked_words.len(), count);
+    }
+}
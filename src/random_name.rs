@@ -0,0 +1,101 @@
+//! Random nickname generation
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+const ONSETS: &[&str] = &[
+    "b", "br", "d", "dr", "f", "g", "gr", "k", "kr", "l", "m", "n", "p", "r", "s", "sh", "st", "t",
+    "th", "v", "z",
+];
+const VOWELS: &[&str] = &["a", "e", "i", "o", "u", "ae", "ai", "ia", "ou"];
+const CODAS: &[&str] = &["", "", "", "n", "r", "l", "s", "th", "k", "m"];
+
+const MAX_NAME_LEN: usize = 32;
+
+pub(crate) const DEFAULT_MIN_SYLLABLES: usize = 2;
+pub(crate) const DEFAULT_MAX_SYLLABLES: usize = 3;
+
+/// Generates a pronounceable fantasy-style name of `min_syllables..=max_syllables` syllables.
+///
+/// Syllables are taken from `seed_words` when any are given, so a guild can steer the
+/// generator towards names that sound like its own theme; otherwise they are built from
+/// common onset, vowel and coda sounds.
+pub(crate) fn pronounceable_name(
+    seed_words: &[String],
+    min_syllables: usize,
+    max_syllables: usize,
+) -> String {
+    let mut rng = rand::thread_rng();
+
+    // A syllable that could never fit in a nickname on its own is no use, which can happen
+    // with long seed words that have no vowels to split on
+    let seed_syllables: Vec<String> = seed_words
+        .iter()
+        .flat_map(|w| syllables(w))
+        .filter(|syllable| syllable.chars().count() <= MAX_NAME_LEN)
+        .collect();
+    let min_syllables = min_syllables.max(1);
+    let syllable_count = rng.gen_range(min_syllables..=max_syllables.max(min_syllables));
+
+    let mut name = String::new();
+    for _ in 0..syllable_count {
+        let syllable = match seed_syllables.choose(&mut rng) {
+            Some(syllable) => syllable.clone(),
+            None => format!(
+                "{}{}{}",
+                ONSETS.choose(&mut rng).unwrap(),
+                VOWELS.choose(&mut rng).unwrap(),
+                CODAS.choose(&mut rng).unwrap()
+            ),
+        };
+        if name.chars().count() + syllable.chars().count() > MAX_NAME_LEN {
+            break;
+        }
+        name.push_str(&syllable);
+    }
+
+    capitalize(&name)
+}
+
+/// Splits a word into rough syllables, cutting between vowels and consonants the way
+/// English speakers tend to (dra-gon, mer-lin)
+fn syllables(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphabetic())
+        .collect();
+    let is_vowel_at = |i: usize| chars.get(i).is_some_and(|c| is_vowel(*c));
+    let is_consonant_at = |i: usize| chars.get(i).is_some_and(|c| !is_vowel(*c));
+
+    let mut syllables = Vec::new();
+    let mut current = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        current.push(*c);
+        let split_after_vowel = is_vowel_at(i) && is_consonant_at(i + 1) && is_vowel_at(i + 2);
+        let split_between_consonants = i > 0
+            && is_vowel_at(i - 1)
+            && is_consonant_at(i)
+            && is_consonant_at(i + 1)
+            && is_vowel_at(i + 2);
+        if split_after_vowel || split_between_consonants {
+            syllables.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        syllables.push(current);
+    }
+    syllables
+}
+
+fn is_vowel(c: char) -> bool {
+    matches!(c, 'a' | 'e' | 'i' | 'o' | 'u' | 'y')
+}
+
+fn capitalize(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
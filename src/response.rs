@@ -0,0 +1,182 @@
+//! Consistently styled replies
+//!
+//! Replies are embeds coloured by their [`Tone`] and are ephemeral unless made public.
+//! Public replies about a rename carry buttons for following up on it.
+
+use poise::serenity_prelude as serenity;
+use poise::serenity_prelude::{
+    ButtonStyle, CreateComponents, CreateEmbed, CreateInteractionResponseData,
+};
+use poise::CreateReply;
+
+use crate::commands::{ComponentAction, Data, Error};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Tone {
+    Success,
+    Info,
+    Warning,
+    Error,
+}
+
+impl Tone {
+    fn colour(&self) -> u32 {
+        match self {
+            Tone::Success => 0x57f287,
+            Tone::Info => 0x5865f2,
+            Tone::Warning => 0xfee75c,
+            Tone::Error => 0xed4245,
+        }
+    }
+
+    fn emoji(&self) -> &'static str {
+        match self {
+            Tone::Success => "✅",
+            Tone::Info => "ℹ️",
+            Tone::Warning => "⚠️",
+            Tone::Error => "❌",
+        }
+    }
+}
+
+pub(crate) struct Response {
+    tone: Tone,
+    pub(crate) content: String,
+    ephemeral: bool,
+    /// History entry the reply is about, shown so it can be looked up later
+    history_id: Option<u64>,
+}
+
+impl Response {
+    fn new(tone: Tone, content: impl Into<String>) -> Self {
+        Self {
+            tone,
+            content: content.into(),
+            ephemeral: true,
+            history_id: None,
+        }
+    }
+
+    pub(crate) fn success(content: impl Into<String>) -> Self {
+        Self::new(Tone::Success, content)
+    }
+
+    pub(crate) fn info(content: impl Into<String>) -> Self {
+        Self::new(Tone::Info, content)
+    }
+
+    pub(crate) fn error(content: impl Into<String>) -> Self {
+        Self::new(Tone::Error, content)
+    }
+
+    pub(crate) fn is_success(&self) -> bool {
+        self.tone == Tone::Success
+    }
+
+    /// Shows the reply to everyone in the channel
+    pub(crate) fn public(mut self) -> Self {
+        self.ephemeral = false;
+        self
+    }
+
+    pub(crate) fn history_id(mut self, id: u64) -> Self {
+        self.history_id = Some(id);
+        self
+    }
+
+    /// Adds a warning line, downgrading a success to a warning
+    pub(crate) fn warn(mut self, warning: &str) -> Self {
+        self.content = format!("{}\n{} {}", self.content, Tone::Warning.emoji(), warning);
+        if self.tone == Tone::Success {
+            self.tone = Tone::Warning;
+        }
+        self
+    }
+
+    /// Fills in a reply, for sending or for editing an earlier one
+    pub(crate) fn build<'a, 'b>(&self, m: &'b mut CreateReply<'a>) -> &'b mut CreateReply<'a> {
+        m.ephemeral(self.ephemeral).embed(|e| self.embed(e));
+        if let Some(id) = self.announced_rename() {
+            m.components(|c| Self::rename_buttons(c, id));
+        }
+        m
+    }
+
+    /// Fills in the response to a button press or modal submission
+    pub(crate) fn build_interaction<'a, 'b>(
+        &self,
+        d: &'b mut CreateInteractionResponseData<'a>,
+    ) -> &'b mut CreateInteractionResponseData<'a> {
+        d.ephemeral(self.ephemeral).embed(|e| self.embed(e));
+        if let Some(id) = self.announced_rename() {
+            d.components(|c| Self::rename_buttons(c, id));
+        }
+        d
+    }
+
+    fn embed<'b>(&self, e: &'b mut CreateEmbed) -> &'b mut CreateEmbed {
+        e.colour(self.tone.colour())
+            .description(format!("{} {}", self.tone.emoji(), self.content));
+        if let Some(id) = self.history_id {
+            e.footer(|f| f.text(format!("History ID {}", id)));
+        }
+        e
+    }
+
+    /// History ID of the rename this reply announces to the channel, if it does
+    fn announced_rename(&self) -> Option<u64> {
+        self.history_id.filter(|_| !self.ephemeral)
+    }
+
+    fn rename_buttons(c: &mut CreateComponents, history_id: u64) -> &mut CreateComponents {
+        c.create_action_row(|r| {
+            r.create_button(|b| {
+                b.custom_id(ComponentAction::RenameAgain(history_id).custom_id())
+                    .label("Rename again")
+                    .style(ButtonStyle::Primary)
+            })
+            .create_button(|b| {
+                b.custom_id(ComponentAction::Undo(history_id).custom_id())
+                    .label("Undo")
+                    .style(ButtonStyle::Danger)
+            })
+            .create_button(|b| {
+                b.custom_id(ComponentAction::ViewHistory(history_id).custom_id())
+                    .label("View history")
+                    .style(ButtonStyle::Secondary)
+            })
+        })
+    }
+
+    /// Sends the reply to a command. Prefix commands can't reply ephemerally, so private
+    /// replies to them go by DM instead; if that fails, the channel is only told to check
+    /// DM settings, so nothing private leaks into it.
+    pub(crate) async fn send(
+        &self,
+        ctx: poise::Context<'_, Data, Error>,
+    ) -> Result<(), serenity::Error> {
+        let private_channel = ctx.guild_id().is_none();
+        if !self.ephemeral || private_channel || matches!(ctx, poise::Context::Application(_)) {
+            ctx.send(|m| self.build(m)).await?;
+            return Ok(());
+        }
+
+        let author = ctx.author();
+        let notice = if ctx.data().dm_limiter.allow(author.id) {
+            let dm = author
+                .direct_message(ctx.serenity_context(), |m| m.embed(|e| self.embed(e)))
+                .await;
+            match dm {
+                Ok(_) => return Ok(()),
+                Err(_) => Response::error(
+                    "The reply is private, but it couldn't be sent by DM; allow DMs from server members and try again.",
+                ),
+            }
+        } else {
+            Response::error("Too many private replies sent by DM; try again in a minute.")
+        };
+        let notice = notice.public();
+        ctx.send(|m| notice.build(m)).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+//! Limits how many private replies each user is sent by DM, so a user repeating a prefix
+//! command can't make the app spam their DMs
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use poise::serenity_prelude::UserId;
+
+/// Most DMs a user is sent within [`DM_WINDOW`]
+const DM_LIMIT: usize = 5;
+
+const DM_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+pub(crate) struct DmLimiter {
+    /// When each user was recently sent a DM, oldest first
+    sent: Mutex<HashMap<UserId, VecDeque<Instant>>>,
+}
+
+impl DmLimiter {
+    /// Records a DM to the user if they are under the limit, returning whether it may be
+    /// sent
+    pub(crate) fn allow(&self, user_id: UserId) -> bool {
+        let now = Instant::now();
+        let mut sent = self.sent.lock().unwrap();
+        sent.retain(|_, times| {
+            while times.front().map_or(false, |time| now - *time >= DM_WINDOW) {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+
+        let times = sent.entry(user_id).or_default();
+        if times.len() >= DM_LIMIT {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_each_user_separately() {
+        let limiter = DmLimiter::default();
+        for _ in 0..DM_LIMIT {
+            assert!(limiter.allow(UserId(1)));
+        }
+        assert!(!limiter.allow(UserId(1)));
+        assert!(limiter.allow(UserId(2)));
+    }
+}
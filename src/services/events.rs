@@ -0,0 +1,58 @@
+//! Changes published inside the app for features that react to them
+//!
+//! Features subscribe to the [`EventBus`] instead of being called from every command that
+//! can make the change, so adding one doesn't mean touching /rename and its siblings.
+
+use poise::serenity_prelude::GuildId;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use tracing::warn;
+
+use super::storage::HistoryEntry;
+
+/// How many events a slow subscriber can fall behind before it starts missing them
+const CAPACITY: usize = 256;
+
+#[derive(Clone)]
+pub(crate) enum Event {
+    /// A nickname change was recorded in history, whether it was made through this app
+    /// or directly in Discord
+    Rename(HistoryEntry),
+    /// A guild's configuration changed, described as in its configuration history
+    ConfigChanged { guild_id: GuildId, change: String },
+}
+
+pub(crate) struct EventBus {
+    sender: Sender<Event>,
+}
+
+impl EventBus {
+    pub(crate) fn new() -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+        }
+    }
+
+    /// Sends an event to every current subscriber. Nobody listening isn't an error.
+    pub(crate) fn publish(&self, event: Event) {
+        let _ = self.sender.send(event);
+    }
+
+    pub(crate) fn subscribe(&self) -> Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+/// Waits for a subscriber's next event, skipping past any it fell too far behind to
+/// receive. Returns `None` once the bus is gone.
+pub(crate) async fn next_event(events: &mut Receiver<Event>, subscriber: &str) -> Option<Event> {
+    loop {
+        match events.recv().await {
+            Ok(event) => return Some(event),
+            Err(RecvError::Lagged(missed)) => {
+                warn!("{} fell behind and missed {} event(s)", subscriber, missed)
+            }
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
@@ -0,0 +1,376 @@
+//! Upgrades databases written by older versions of the app to the current layout
+//!
+//! The data database stores the version of the layout it was written with. At startup,
+//! every migration after that version runs in order, once the data directory has been
+//! backed up. Each migration's changes are applied in one transaction together with the
+//! new version, so a crash part way through never leaves a migration half applied.
+
+use std::fs;
+use std::path::Path;
+
+use sled::transaction::TransactionResult;
+use sled::Transactional;
+use tracing::info;
+
+use super::storage::unix_now;
+use crate::commands::Error;
+
+/// Key in the data database's default tree holding the schema version
+pub(crate) const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// Tree recording when each migration was applied, keyed by version
+pub(crate) const MIGRATIONS_TREE: &str = "migrations";
+
+/// Tree holding role names from before roles were stored by ID, until each guild connects
+/// and the names can be looked up; see
+/// [`UnresolvedRoleNameDb`](super::storage::UnresolvedRoleNameDb)
+pub(crate) const UNRESOLVED_ROLE_NAMES_TREE: &str = "unresolved_role_names";
+
+/// Databases the app has kept under the data directory, current or not
+const DATABASES: [&str; 3] = ["renamer_data", "renamer_roles", "allow_roles"];
+
+/// Role trees, which used to be databases of their own, with the app role each is for
+const ROLE_TREES: [(&str, &str); 2] = [("renamer_roles", "Renamer"), ("allow_roles", "Allow")];
+
+/// Changes a migration makes, as a batch for each tree it touches
+type Plan = Vec<(sled::Tree, sled::Batch)>;
+
+/// Removes what a migration left behind outside the data database
+type CleanUp = fn(&Path) -> Result<(), Error>;
+
+pub(crate) struct Migration {
+    pub(crate) version: u64,
+    pub(crate) description: &'static str,
+    /// Works out the changes from the data directory and database as they are
+    plan: fn(&Path, &sled::Db) -> Result<Plan, Error>,
+    /// Runs once the migration's changes are committed
+    clean_up: Option<CleanUp>,
+}
+
+/// Every migration, oldest first. The last one's version is the current schema version.
+pub(crate) const MIGRATIONS: [Migration; 3] = [
+    Migration {
+        version: 1,
+        description: "Move role names into trees of the data database",
+        plan: merge_role_databases,
+        clean_up: Some(remove_role_databases),
+    },
+    Migration {
+        version: 2,
+        description: "Store IDs and timestamps in keys and values as big-endian",
+        plan: use_big_endian,
+        clean_up: None,
+    },
+    Migration {
+        version: 3,
+        description: "Store role IDs instead of role names",
+        plan: set_role_names_aside,
+        clean_up: None,
+    },
+];
+
+pub(crate) fn latest_version() -> u64 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+/// Looks up a migration's description by version
+pub(crate) fn describe(version: u64) -> &'static str {
+    MIGRATIONS
+        .iter()
+        .find(|migration| migration.version == version)
+        .map_or("Unknown migration", |migration| migration.description)
+}
+
+pub(crate) fn schema_version(data: &sled::Db) -> Result<u64, Error> {
+    Ok(data.get(SCHEMA_VERSION_KEY)?.map_or(0, |value| {
+        u64::from_be_bytes(value.as_ref().try_into().unwrap())
+    }))
+}
+
+/// Opens the data database in the data directory, first migrating it and the databases
+/// it replaced to the current schema version
+pub(crate) fn open_migrated(dir: &Path) -> Result<sled::Db, Error> {
+    let fresh = DATABASES.iter().all(|name| !dir.join(name).exists());
+    let data = sled::open(dir.join("renamer_data"))?;
+    if fresh {
+        data.insert(SCHEMA_VERSION_KEY, &latest_version().to_be_bytes())?;
+        return Ok(data);
+    }
+
+    let version = schema_version(&data)?;
+    if version > latest_version() {
+        return Err(format!(
+            "The databases are at schema version {}, but this build only knows up to {}",
+            version,
+            latest_version()
+        )
+        .into());
+    }
+    if version == latest_version() {
+        return Ok(data);
+    }
+
+    // Copying a database that is still open could catch it mid-write
+    data.flush()?;
+    drop(data);
+    let backup = dir
+        .join("backups")
+        .join(format!("{}-v{}", unix_now(), version));
+    for name in DATABASES {
+        let path = dir.join(name);
+        if path.exists() {
+            copy_dir(&path, &backup.join(name))?;
+        }
+    }
+    info!(
+        "Backed up the databases to {} before migrating",
+        backup.display()
+    );
+
+    let data = sled::open(dir.join("renamer_data"))?;
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > version)
+    {
+        info!(
+            "Migrating the databases to schema version {}: {}",
+            migration.version, migration.description
+        );
+        apply(dir, &data, migration).map_err(|e| {
+            format!(
+                "Migration to schema version {} failed, restore the backup in {}: {}",
+                migration.version,
+                backup.display(),
+                e
+            )
+        })?;
+    }
+    Ok(data)
+}
+
+/// Commits a migration's changes, the new schema version and when it was applied in one
+/// transaction
+fn apply(dir: &Path, data: &sled::Db, migration: &Migration) -> Result<(), Error> {
+    let plan = (migration.plan)(dir, data)?;
+    let mut trees: Vec<sled::Tree> = plan.iter().map(|(tree, _)| tree.clone()).collect();
+    trees.push(sled::Tree::clone(data));
+    trees.push(data.open_tree(MIGRATIONS_TREE)?);
+
+    let result: TransactionResult<(), sled::Error> = trees.as_slice().transaction(|views| {
+        for (view, (_, batch)) in views.iter().zip(&plan) {
+            view.apply_batch(batch)?;
+        }
+        let [.., default, applied] = views.as_slice() else {
+            unreachable!()
+        };
+        default.insert(SCHEMA_VERSION_KEY, &migration.version.to_be_bytes())?;
+        applied.insert(&migration.version.to_be_bytes(), &unix_now().to_be_bytes())?;
+        Ok(())
+    });
+    result?;
+    data.flush()?;
+
+    if let Some(clean_up) = migration.clean_up {
+        clean_up(dir)?;
+    }
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<(), Error> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Version 1: role names were kept in two databases of their own beside the data database
+fn merge_role_databases(dir: &Path, data: &sled::Db) -> Result<Plan, Error> {
+    let mut plan = Vec::new();
+    for (name, _) in ROLE_TREES {
+        let path = dir.join(name);
+        if !path.exists() {
+            continue;
+        }
+        let old = sled::open(&path)?;
+        let mut batch = sled::Batch::default();
+        for item in old.iter() {
+            let (key, value) = item?;
+            batch.insert(key, value);
+        }
+        plan.push((data.open_tree(name)?, batch));
+    }
+    Ok(plan)
+}
+
+fn remove_role_databases(dir: &Path) -> Result<(), Error> {
+    for (name, _) in ROLE_TREES {
+        let path = dir.join(name);
+        if path.exists() {
+            fs::remove_dir_all(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// What a tree's values are made of, for converting them to big-endian
+#[derive(Clone, Copy)]
+enum Value {
+    /// Strings, JSON or nothing, none of which depend on byte order
+    Unaffected,
+    /// A list of role IDs
+    Ids,
+    /// A Unix timestamp
+    Timestamp,
+}
+
+/// Trees with IDs in their keys, as how many IDs each key starts with and what the values
+/// are made of
+const BYTE_ORDER_TREES: [(&str, usize, Value); 12] = [
+    ("renamer_roles", 1, Value::Unaffected),
+    ("allow_roles", 1, Value::Unaffected),
+    ("created_roles", 2, Value::Unaffected),
+    ("settings", 1, Value::Unaffected),
+    ("global_optouts", 1, Value::Unaffected),
+    ("forget_requests", 1, Value::Timestamp),
+    ("history", 2, Value::Unaffected),
+    ("target_rules", 2, Value::Ids),
+    ("watches", 3, Value::Unaffected),
+    ("disabled_commands", 1, Value::Unaffected),
+    ("config_history", 1, Value::Unaffected),
+    ("consent_campaigns", 2, Value::Unaffected),
+];
+
+/// Version 2: IDs and timestamps were stored in the host's byte order, so scans didn't
+/// return entries in ID order on little-endian hosts
+fn use_big_endian(_dir: &Path, data: &sled::Db) -> Result<Plan, Error> {
+    let mut plan = Vec::new();
+    for (name, key_ids, value_kind) in BYTE_ORDER_TREES {
+        let tree = data.open_tree(name)?;
+        let mut batch = sled::Batch::default();
+        let mut converted = Vec::new();
+        // Every old key is removed before any new one is inserted, since a converted key
+        // can equal another entry's old key
+        for item in tree.iter() {
+            let (key, value) = item?;
+            let value = match value_kind {
+                Value::Unaffected => value.to_vec(),
+                Value::Ids => ne_to_be(&value, value.len() / 8),
+                Value::Timestamp => ne_to_be(&value, 1),
+            };
+            batch.remove(key.clone());
+            converted.push((ne_to_be(&key, key_ids), value));
+        }
+        for (key, value) in converted {
+            batch.insert(key, value);
+        }
+        plan.push((tree, batch));
+    }
+    Ok(plan)
+}
+
+/// Converts the first `count` u64s in the bytes from native to big-endian byte order,
+/// leaving the rest as it is
+fn ne_to_be(bytes: &[u8], count: usize) -> Vec<u8> {
+    let mut converted = bytes.to_vec();
+    for id in converted.chunks_exact_mut(8).take(count) {
+        let value = u64::from_ne_bytes((&*id).try_into().unwrap());
+        id.copy_from_slice(&value.to_be_bytes());
+    }
+    converted
+}
+
+/// Version 3: roles were stored by name, so renaming a role in Discord meant following
+/// the rename. Looking the names up needs each guild's roles, which aren't known until the
+/// guild connects, so the names are set aside until then.
+fn set_role_names_aside(_dir: &Path, data: &sled::Db) -> Result<Plan, Error> {
+    let mut unresolved = sled::Batch::default();
+    let mut plan = Vec::new();
+    for (name, app_role) in ROLE_TREES {
+        let tree = data.open_tree(name)?;
+        let mut batch = sled::Batch::default();
+        for item in tree.iter() {
+            let (guild_key, role_name) = item?;
+            let mut key = guild_key.to_vec();
+            key.extend_from_slice(app_role.as_bytes());
+            unresolved.insert(key, role_name);
+            batch.remove(guild_key);
+        }
+        plan.push((tree, batch));
+    }
+    plan.push((data.open_tree(UNRESOLVED_ROLE_NAMES_TREE)?, unresolved));
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn converts_only_leading_ids() {
+        let mut key = 42u64.to_ne_bytes().to_vec();
+        key.extend_from_slice(b"SelfService");
+        let mut expected = 42u64.to_be_bytes().to_vec();
+        expected.extend_from_slice(b"SelfService");
+        assert_eq!(ne_to_be(&key, 1), expected);
+    }
+
+    #[test]
+    fn migrates_the_oldest_layout() {
+        let dir = env::temp_dir().join(format!("renamer-migrations-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let guild: u64 = 1 << 40;
+        let user: u64 = 2 << 40;
+
+        let roles = sled::open(dir.join("renamer_roles")).unwrap();
+        roles.insert(guild.to_ne_bytes(), "Renamers").unwrap();
+        drop(roles);
+        let data = sled::open(dir.join("renamer_data")).unwrap();
+        let mut setting = guild.to_ne_bytes().to_vec();
+        setting.extend_from_slice(b"SelfService");
+        let settings = data.open_tree("settings").unwrap();
+        settings.insert(&setting, "true").unwrap();
+        let forget_requests = data.open_tree("forget_requests").unwrap();
+        forget_requests
+            .insert(user.to_ne_bytes(), &1_700_000_000u64.to_ne_bytes())
+            .unwrap();
+        drop((settings, forget_requests, data));
+
+        let data = open_migrated(&dir).unwrap();
+        assert_eq!(schema_version(&data).unwrap(), latest_version());
+        assert_eq!(
+            data.open_tree(MIGRATIONS_TREE).unwrap().len(),
+            MIGRATIONS.len()
+        );
+        assert!(!dir.join("renamer_roles").exists());
+        assert!(dir.join("backups").read_dir().unwrap().next().is_some());
+
+        assert!(data.open_tree("renamer_roles").unwrap().is_empty());
+        let mut role_key = guild.to_be_bytes().to_vec();
+        role_key.extend_from_slice(b"Renamer");
+        let unresolved = data.open_tree(UNRESOLVED_ROLE_NAMES_TREE).unwrap();
+        assert_eq!(unresolved.get(role_key).unwrap().unwrap(), "Renamers");
+
+        let mut setting = guild.to_be_bytes().to_vec();
+        setting.extend_from_slice(b"SelfService");
+        let settings = data.open_tree("settings").unwrap();
+        assert_eq!(settings.len(), 1);
+        assert_eq!(settings.get(setting).unwrap().unwrap(), "true");
+        let forget_requests = data.open_tree("forget_requests").unwrap();
+        assert_eq!(
+            forget_requests.get(user.to_be_bytes()).unwrap().unwrap(),
+            &1_700_000_000u64.to_be_bytes()
+        );
+
+        drop((settings, forget_requests, unresolved, data));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,7 @@
+//! State and rules shared by every command, independent of Discord interactions
+
+pub(crate) mod dm_limiter;
+pub(crate) mod events;
+pub(crate) mod migrations;
+pub(crate) mod storage;
+pub(crate) mod validation;
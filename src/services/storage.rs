@@ -0,0 +1,1252 @@
+//! Everything the app stores, in trees of a sled database under the data directory
+//!
+//! [`Storage`] opens every tree once at startup, after [`migrations`] brings older layouts
+//! up to date, and is shared with commands and background tasks through
+//! [`Data`](crate::commands::Data). IDs in keys are big-endian so that scans return
+//! entries in ID order.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use poise::serenity_prelude::{GuildId, RoleId, UserId};
+use serde::{Deserialize, Serialize};
+use strum_macros::Display;
+
+use self::AppRole::*;
+use super::events::{Event, EventBus};
+use super::migrations::{self, MIGRATIONS_TREE, UNRESOLVED_ROLE_NAMES_TREE};
+use crate::commands::Error;
+use crate::environment::data_dir;
+
+/// Handles to every database, each wrapped in a type that knows its key layout
+pub(crate) struct Storage {
+    pub(crate) roles: RoleDb,
+    pub(crate) created_roles: CreatedRoleDb,
+    pub(crate) settings: SettingsDb,
+    pub(crate) optouts: OptOutDb,
+    pub(crate) forget_requests: ForgetRequestDb,
+    pub(crate) history: HistoryDb,
+    pub(crate) target_rules: TargetRuleDb,
+    pub(crate) watches: WatchDb,
+    pub(crate) usage: UsageDb,
+    pub(crate) disabled_commands: DisabledCommandDb,
+    pub(crate) staff_approvals: StaffApprovalDb,
+    pub(crate) config_history: ConfigHistoryDb,
+    pub(crate) consent_campaigns: ConsentCampaignDb,
+    pub(crate) migrations: MigrationDb,
+    pub(crate) pending_renames: PendingRenameDb,
+    pub(crate) unresolved_role_names: UnresolvedRoleNameDb,
+    /// Published to whenever a rename or configuration change is stored
+    pub(crate) events: EventBus,
+}
+
+impl Storage {
+    pub(crate) fn open() -> Result<Self, Error> {
+        let data = migrations::open_migrated(&data_dir())?;
+        let pending_renames = data.open_tree("pending_renames")?;
+        // Renames that were in progress when the app stopped will never be recorded
+        pending_renames.clear()?;
+        Ok(Self {
+            roles: RoleDb {
+                renamer_roles: data.open_tree("renamer_roles")?,
+                allow_roles: data.open_tree("allow_roles")?,
+            },
+            created_roles: CreatedRoleDb {
+                created_roles: data.open_tree("created_roles")?,
+            },
+            settings: SettingsDb {
+                settings: data.open_tree("settings")?,
+            },
+            optouts: OptOutDb {
+                global_optouts: data.open_tree("global_optouts")?,
+            },
+            forget_requests: ForgetRequestDb {
+                forget_requests: data.open_tree("forget_requests")?,
+            },
+            history: HistoryDb {
+                history: data.open_tree("history")?,
+                ids: data.clone(),
+            },
+            target_rules: TargetRuleDb {
+                target_rules: data.open_tree("target_rules")?,
+            },
+            watches: WatchDb {
+                watches: data.open_tree("watches")?,
+            },
+            usage: UsageDb {
+                command_stats: data.open_tree("command_stats")?,
+            },
+            disabled_commands: DisabledCommandDb {
+                disabled_commands: data.open_tree("disabled_commands")?,
+            },
+            staff_approvals: StaffApprovalDb {
+                staff_approvals: data.open_tree("staff_approvals")?,
+                ids: data.clone(),
+            },
+            config_history: ConfigHistoryDb {
+                config_history: data.open_tree("config_history")?,
+                ids: data.clone(),
+            },
+            consent_campaigns: ConsentCampaignDb {
+                consent_campaigns: data.open_tree("consent_campaigns")?,
+                ids: data.clone(),
+            },
+            migrations: MigrationDb {
+                migrations: data.open_tree(MIGRATIONS_TREE)?,
+                data,
+            },
+            pending_renames: PendingRenameDb { pending_renames },
+            unresolved_role_names: UnresolvedRoleNameDb {
+                unresolved_role_names: data.open_tree(UNRESOLVED_ROLE_NAMES_TREE)?,
+            },
+            events: EventBus::new(),
+        })
+    }
+
+    /// Changes a setting, first saving the guild's configuration if the value differs
+    pub(crate) fn set_setting(
+        &self,
+        setting: Setting,
+        guild_id: &GuildId,
+        value: &str,
+    ) -> Result<Option<String>, Error> {
+        if self.settings.get(setting, guild_id)?.as_deref() == Some(value) {
+            return self.settings.insert(setting, guild_id, value);
+        }
+        self.record_config(guild_id, &setting.to_string())?;
+        let previous = self.settings.insert(setting, guild_id, value)?;
+        self.config_changed(guild_id, &setting.to_string());
+        Ok(previous)
+    }
+
+    /// Changes a role, first saving the guild's configuration if the role differs
+    pub(crate) fn set_role(
+        &self,
+        app_role: AppRole,
+        guild_id: &GuildId,
+        role_id: &RoleId,
+    ) -> Result<Option<RoleId>, Error> {
+        let change = format!("{} role", app_role);
+        if self.roles.get(app_role, guild_id)? == Some(*role_id) {
+            return self.roles.insert(app_role, guild_id, role_id);
+        }
+        self.record_config(guild_id, &change)?;
+        let previous = self.roles.insert(app_role, guild_id, role_id)?;
+        self.config_changed(guild_id, &change);
+        Ok(previous)
+    }
+
+    /// Changes which roles members of the actor role may not rename, first saving the
+    /// guild's configuration if the rule differs
+    pub(crate) fn set_target_rules(
+        &self,
+        guild_id: &GuildId,
+        actor_role_id: &RoleId,
+        protected_role_ids: &[RoleId],
+    ) -> Result<(), Error> {
+        let change = "target rules";
+        if self.target_rules.get(guild_id, actor_role_id)? == protected_role_ids {
+            return Ok(());
+        }
+        self.record_config(guild_id, change)?;
+        self.target_rules
+            .insert(guild_id, actor_role_id, protected_role_ids)?;
+        self.config_changed(guild_id, change);
+        Ok(())
+    }
+
+    /// Turns a command off in the guild, first saving the guild's configuration. Returns
+    /// whether the command was already disabled.
+    pub(crate) fn disable_command(&self, guild_id: &GuildId, command: &str) -> Result<bool, Error> {
+        let change = "disabled commands";
+        if self.disabled_commands.contains(guild_id, command)? {
+            return Ok(true);
+        }
+        self.record_config(guild_id, change)?;
+        self.disabled_commands.insert(guild_id, command)?;
+        self.config_changed(guild_id, change);
+        Ok(false)
+    }
+
+    /// Turns a disabled command back on in the guild, first saving the guild's
+    /// configuration. Returns whether the command was disabled.
+    pub(crate) fn enable_command(&self, guild_id: &GuildId, command: &str) -> Result<bool, Error> {
+        let change = "disabled commands";
+        if !self.disabled_commands.contains(guild_id, command)? {
+            return Ok(false);
+        }
+        self.record_config(guild_id, change)?;
+        self.disabled_commands.remove(guild_id, command)?;
+        self.config_changed(guild_id, change);
+        Ok(true)
+    }
+
+    /// Tells subscribers a configuration change has been stored
+    pub(crate) fn config_changed(&self, guild_id: &GuildId, change: &str) {
+        self.events.publish(Event::ConfigChanged {
+            guild_id: *guild_id,
+            change: change.to_string(),
+        });
+    }
+
+    /// Adds a nickname change to history and tells subscribers about it, returning the
+    /// entry's ID
+    pub(crate) fn record_rename(&self, mut entry: HistoryEntry) -> Result<u64, Error> {
+        let id = self.history.insert(entry.clone())?;
+        entry.id = id;
+        self.events.publish(Event::Rename(entry));
+        Ok(id)
+    }
+
+    /// Saves the guild's current configuration before something in it changes
+    pub(crate) fn record_config(&self, guild_id: &GuildId, change: &str) -> Result<(), Error> {
+        self.config_history.insert(
+            guild_id,
+            ConfigSnapshot {
+                id: 0,
+                timestamp: unix_now(),
+                change: change.to_string(),
+                settings: self.settings.all(guild_id)?,
+                renamer_role: None,
+                allow_role: None,
+                renamer_role_id: self.roles.get(Renamer, guild_id)?.map(|id| id.0),
+                allow_role_id: self.roles.get(Allow, guild_id)?.map(|id| id.0),
+                target_rules: self.target_rules.all(guild_id)?,
+                disabled_commands: self.disabled_commands.list(guild_id)?,
+            },
+        )
+    }
+}
+
+/// Concatenates two IDs into a key that can be prefix-scanned by the first
+fn pair_key(first: u64, second: u64) -> [u8; 16] {
+    let mut key = [0; 16];
+    key[..8].copy_from_slice(&first.to_be_bytes());
+    key[8..].copy_from_slice(&second.to_be_bytes());
+    key
+}
+
+fn second_of_pair_key(key: &[u8]) -> u64 {
+    u64::from_be_bytes(key[8..16].try_into().unwrap())
+}
+
+pub(crate) struct RoleDb {
+    renamer_roles: sled::Tree,
+    allow_roles: sled::Tree,
+}
+
+impl RoleDb {
+    pub(crate) fn get(&self, app_role: AppRole, key: &GuildId) -> Result<Option<RoleId>, Error> {
+        let bytes = key.0.to_be_bytes();
+        let result = self.get_db(app_role).get(bytes)?;
+        Ok(result.map(|val| RoleId(u64::from_be_bytes(val.as_ref().try_into().unwrap()))))
+    }
+
+    /// Sets the role without recording the change in the config history; see
+    /// [`Storage::set_role`]
+    fn insert(
+        &self,
+        app_role: AppRole,
+        key: &GuildId,
+        role_id: &RoleId,
+    ) -> Result<Option<RoleId>, Error> {
+        let key_bytes = key.0.to_be_bytes();
+        let prev_val = self
+            .get_db(app_role)
+            .insert(key_bytes, &role_id.0.to_be_bytes())?;
+        Ok(prev_val.map(|val| RoleId(u64::from_be_bytes(val.as_ref().try_into().unwrap()))))
+    }
+
+    /// Sets or clears the role without recording the change in the config history
+    pub(crate) fn restore(
+        &self,
+        app_role: AppRole,
+        key: &GuildId,
+        role_id: Option<RoleId>,
+    ) -> Result<(), Error> {
+        let key_bytes = key.0.to_be_bytes();
+        match role_id {
+            Some(role_id) => self
+                .get_db(app_role)
+                .insert(key_bytes, &role_id.0.to_be_bytes())?,
+            None => self.get_db(app_role).remove(key_bytes)?,
+        };
+        Ok(())
+    }
+
+    fn get_db(&self, app_role: AppRole) -> &sled::Tree {
+        match app_role {
+            Renamer => &self.renamer_roles,
+            Allow => &self.allow_roles,
+        }
+    }
+}
+
+/// Role names from before roles were stored by ID, keyed by guild and app role, waiting
+/// for their guild to connect so they can be looked up
+pub(crate) struct UnresolvedRoleNameDb {
+    unresolved_role_names: sled::Tree,
+}
+
+impl UnresolvedRoleNameDb {
+    /// Removes and returns the name the app role had in the guild, if it hasn't been
+    /// looked up yet
+    pub(crate) fn take(
+        &self,
+        app_role: AppRole,
+        guild_id: &GuildId,
+    ) -> Result<Option<String>, Error> {
+        let mut key = guild_id.0.to_be_bytes().to_vec();
+        key.extend_from_slice(app_role.to_string().as_bytes());
+        match self.unresolved_role_names.remove(key)? {
+            Some(value) => Ok(Some(String::from_utf8(value.to_vec())?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Per-guild rules listing which roles members of a given role may not rename
+pub(crate) struct TargetRuleDb {
+    target_rules: sled::Tree,
+}
+
+impl TargetRuleDb {
+    pub(crate) fn get(
+        &self,
+        guild_id: &GuildId,
+        actor_role_id: &RoleId,
+    ) -> Result<Vec<RoleId>, Error> {
+        let result = self
+            .target_rules
+            .get(pair_key(guild_id.0, actor_role_id.0))?;
+        let role_ids = result
+            .map(|val| {
+                val.chunks_exact(8)
+                    .map(|id| RoleId(u64::from_be_bytes(id.try_into().unwrap())))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(role_ids)
+    }
+
+    /// Sets the rule without recording the change in the config history; see
+    /// [`Storage::set_target_rules`]
+    fn insert(
+        &self,
+        guild_id: &GuildId,
+        actor_role_id: &RoleId,
+        protected_role_ids: &[RoleId],
+    ) -> Result<(), Error> {
+        let key = pair_key(guild_id.0, actor_role_id.0);
+        if protected_role_ids.is_empty() {
+            self.target_rules.remove(key)?;
+        } else {
+            let value: Vec<u8> = protected_role_ids
+                .iter()
+                .flat_map(|id| id.0.to_be_bytes())
+                .collect();
+            self.target_rules.insert(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Every rule in the guild, as protected role IDs by actor role ID
+    pub(crate) fn all(&self, guild_id: &GuildId) -> Result<BTreeMap<u64, Vec<u64>>, Error> {
+        self.target_rules
+            .scan_prefix(guild_id.0.to_be_bytes())
+            .map(|item| {
+                let (key, value) = item?;
+                let role_ids = value
+                    .chunks_exact(8)
+                    .map(|id| u64::from_be_bytes(id.try_into().unwrap()))
+                    .collect();
+                Ok((second_of_pair_key(&key), role_ids))
+            })
+            .collect()
+    }
+
+    /// Replaces all of the guild's rules without recording the change in the config
+    /// history
+    pub(crate) fn restore(
+        &self,
+        guild_id: &GuildId,
+        rules: &BTreeMap<u64, Vec<u64>>,
+    ) -> Result<(), Error> {
+        for key in self
+            .target_rules
+            .scan_prefix(guild_id.0.to_be_bytes())
+            .keys()
+        {
+            self.target_rules.remove(key?)?;
+        }
+        for (actor_role_id, protected_role_ids) in rules {
+            let protected_role_ids: Vec<RoleId> =
+                protected_role_ids.iter().map(|id| RoleId(*id)).collect();
+            self.insert(guild_id, &RoleId(*actor_role_id), &protected_role_ids)?;
+        }
+        Ok(())
+    }
+}
+
+/// Renames sent to Discord but not yet recorded in history, keyed by guild and member, so
+/// the member update they cause isn't mistaken for a change made outside the app
+pub(crate) struct PendingRenameDb {
+    pending_renames: sled::Tree,
+}
+
+impl PendingRenameDb {
+    pub(crate) fn insert(
+        &self,
+        guild_id: &GuildId,
+        user_id: &UserId,
+        actor_id: &UserId,
+        nickname: &str,
+    ) -> Result<(), Error> {
+        let mut value = actor_id.0.to_be_bytes().to_vec();
+        value.extend_from_slice(nickname.as_bytes());
+        self.pending_renames
+            .insert(pair_key(guild_id.0, user_id.0), value)?;
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self, guild_id: &GuildId, user_id: &UserId) -> Result<(), Error> {
+        self.pending_renames
+            .remove(pair_key(guild_id.0, user_id.0))?;
+        Ok(())
+    }
+
+    /// Who is renaming the member and to what, if a rename is in progress
+    pub(crate) fn get(
+        &self,
+        guild_id: &GuildId,
+        user_id: &UserId,
+    ) -> Result<Option<(UserId, String)>, Error> {
+        match self.pending_renames.get(pair_key(guild_id.0, user_id.0))? {
+            Some(value) => Ok(Some((
+                UserId(u64::from_be_bytes(value[..8].try_into().unwrap())),
+                String::from_utf8(value[8..].to_vec())?,
+            ))),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Moderators watching members' nicknames, keyed by guild, then member, then watcher
+pub(crate) struct WatchDb {
+    watches: sled::Tree,
+}
+
+impl WatchDb {
+    /// Returns whether the watcher was already watching the member
+    pub(crate) fn insert(
+        &self,
+        guild_id: &GuildId,
+        user_id: &UserId,
+        watcher_id: &UserId,
+    ) -> Result<bool, Error> {
+        let prev_val = self
+            .watches
+            .insert(Self::key(guild_id, user_id, watcher_id), &[])?;
+        Ok(prev_val.is_some())
+    }
+
+    /// Returns whether the watcher was watching the member
+    pub(crate) fn remove(
+        &self,
+        guild_id: &GuildId,
+        user_id: &UserId,
+        watcher_id: &UserId,
+    ) -> Result<bool, Error> {
+        let prev_val = self
+            .watches
+            .remove(Self::key(guild_id, user_id, watcher_id))?;
+        Ok(prev_val.is_some())
+    }
+
+    pub(crate) fn watchers(
+        &self,
+        guild_id: &GuildId,
+        user_id: &UserId,
+    ) -> Result<Vec<UserId>, Error> {
+        self.watches
+            .scan_prefix(pair_key(guild_id.0, user_id.0))
+            .keys()
+            .map(|key| Ok(UserId(u64::from_be_bytes(key?[16..24].try_into().unwrap()))))
+            .collect()
+    }
+
+    /// Every watch in any guild by the user or of them, as (guild, member, watcher) IDs
+    pub(crate) fn involving(&self, user_id: &UserId) -> Result<Vec<(u64, u64, u64)>, Error> {
+        let mut watches = Vec::new();
+        for key in self.watches.iter().keys() {
+            let key = key?;
+            let ids: Vec<u64> = key
+                .chunks_exact(8)
+                .map(|id| u64::from_be_bytes(id.try_into().unwrap()))
+                .collect();
+            if ids[1] == user_id.0 || ids[2] == user_id.0 {
+                watches.push((ids[0], ids[1], ids[2]));
+            }
+        }
+        Ok(watches)
+    }
+
+    pub(crate) fn remove_involving(&self, user_id: &UserId) -> Result<(), Error> {
+        for (guild_id, member_id, watcher_id) in self.involving(user_id)? {
+            self.remove(&GuildId(guild_id), &UserId(member_id), &UserId(watcher_id))?;
+        }
+        Ok(())
+    }
+
+    fn key(guild_id: &GuildId, user_id: &UserId, watcher_id: &UserId) -> Vec<u8> {
+        let mut key = pair_key(guild_id.0, user_id.0).to_vec();
+        key.extend_from_slice(&watcher_id.0.to_be_bytes());
+        key
+    }
+}
+
+/// Server roles that were created by this app rather than by an admin
+pub(crate) struct CreatedRoleDb {
+    created_roles: sled::Tree,
+}
+
+impl CreatedRoleDb {
+    pub(crate) fn insert(&self, guild_id: &GuildId, role_id: &RoleId) -> Result<(), Error> {
+        self.created_roles
+            .insert(pair_key(guild_id.0, role_id.0), &[])?;
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self, guild_id: &GuildId, role_id: &RoleId) -> Result<(), Error> {
+        self.created_roles.remove(pair_key(guild_id.0, role_id.0))?;
+        Ok(())
+    }
+
+    pub(crate) fn list(&self, guild_id: &GuildId) -> Result<Vec<RoleId>, Error> {
+        self.created_roles
+            .scan_prefix(guild_id.0.to_be_bytes())
+            .keys()
+            .map(|key| Ok(RoleId(second_of_pair_key(&key?))))
+            .collect()
+    }
+}
+
+/// Per-guild settings, stored as strings keyed by guild and setting name
+pub(crate) struct SettingsDb {
+    settings: sled::Tree,
+}
+
+impl SettingsDb {
+    pub(crate) fn get(
+        &self,
+        setting: Setting,
+        guild_id: &GuildId,
+    ) -> Result<Option<String>, Error> {
+        let result = self.settings.get(Self::key(setting, guild_id))?;
+        let result_mapped = result.map(|val| String::from_utf8(val.to_vec()).unwrap());
+        Ok(result_mapped)
+    }
+
+    /// Sets the setting without recording the change in the config history; see
+    /// [`Storage::set_setting`]
+    fn insert(
+        &self,
+        setting: Setting,
+        guild_id: &GuildId,
+        value: &str,
+    ) -> Result<Option<String>, Error> {
+        let prev_val = self
+            .settings
+            .insert(Self::key(setting, guild_id), value.as_bytes())?;
+        let prev_val_mapped = prev_val.map(|val| String::from_utf8(val.to_vec()).unwrap());
+        Ok(prev_val_mapped)
+    }
+
+    pub(crate) fn get_flag(&self, setting: Setting, guild_id: &GuildId) -> Result<bool, Error> {
+        Ok(self.get(setting, guild_id)?.as_deref() == Some("true"))
+    }
+
+    /// Records a setting the app keeps for itself rather than one an admin configures, such
+    /// as [`Setting::LastSweep`], which config snapshots leave out
+    pub(crate) fn set_state(
+        &self,
+        setting: Setting,
+        guild_id: &GuildId,
+        value: &str,
+    ) -> Result<(), Error> {
+        self.insert(setting, guild_id, value)?;
+        Ok(())
+    }
+
+    /// Every configured setting stored for the guild, by setting name
+    pub(crate) fn all(&self, guild_id: &GuildId) -> Result<BTreeMap<String, String>, Error> {
+        let mut settings = BTreeMap::new();
+        for item in self.settings.scan_prefix(guild_id.0.to_be_bytes()) {
+            let (key, value) = item?;
+            let name = String::from_utf8(key[8..].to_vec())?;
+            if Self::is_state(&name) {
+                continue;
+            }
+            settings.insert(name, String::from_utf8(value.to_vec())?);
+        }
+        Ok(settings)
+    }
+
+    /// Replaces all of the guild's settings without recording the change in the config
+    /// history
+    pub(crate) fn restore(
+        &self,
+        guild_id: &GuildId,
+        settings: &BTreeMap<String, String>,
+    ) -> Result<(), Error> {
+        for key in self.settings.scan_prefix(guild_id.0.to_be_bytes()).keys() {
+            let key = key?;
+            if !Self::is_state(&String::from_utf8_lossy(&key[8..])) {
+                self.settings.remove(key)?;
+            }
+        }
+        for (name, value) in settings {
+            let mut key = guild_id.0.to_be_bytes().to_vec();
+            key.extend_from_slice(name.as_bytes());
+            self.settings.insert(key, value.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn key(setting: Setting, guild_id: &GuildId) -> Vec<u8> {
+        let mut key = guild_id.0.to_be_bytes().to_vec();
+        key.extend_from_slice(setting.to_string().as_bytes());
+        key
+    }
+
+    fn is_state(name: &str) -> bool {
+        name == Setting::LastSweep.to_string()
+    }
+}
+
+/// Users who have opted out of being renamed by this app in every guild
+pub(crate) struct OptOutDb {
+    global_optouts: sled::Tree,
+}
+
+impl OptOutDb {
+    pub(crate) fn contains(&self, user_id: &UserId) -> Result<bool, Error> {
+        Ok(self.global_optouts.contains_key(user_id.0.to_be_bytes())?)
+    }
+
+    /// Returns whether the user was already opted out
+    pub(crate) fn insert(&self, user_id: &UserId) -> Result<bool, Error> {
+        let prev_val = self.global_optouts.insert(user_id.0.to_be_bytes(), &[])?;
+        Ok(prev_val.is_some())
+    }
+
+    /// Returns whether the user was opted out
+    pub(crate) fn remove(&self, user_id: &UserId) -> Result<bool, Error> {
+        let prev_val = self.global_optouts.remove(user_id.0.to_be_bytes())?;
+        Ok(prev_val.is_some())
+    }
+}
+
+/// Pending /renamer forget_me requests, as the Unix time each one becomes due
+pub(crate) struct ForgetRequestDb {
+    forget_requests: sled::Tree,
+}
+
+impl ForgetRequestDb {
+    pub(crate) fn insert(&self, user_id: &UserId, due_at: u64) -> Result<(), Error> {
+        self.forget_requests
+            .insert(user_id.0.to_be_bytes(), &due_at.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Returns whether there was a pending request
+    pub(crate) fn remove(&self, user_id: &UserId) -> Result<bool, Error> {
+        let prev_val = self.forget_requests.remove(user_id.0.to_be_bytes())?;
+        Ok(prev_val.is_some())
+    }
+
+    pub(crate) fn get(&self, user_id: &UserId) -> Result<Option<u64>, Error> {
+        let result = self.forget_requests.get(user_id.0.to_be_bytes())?;
+        Ok(result.map(|val| u64::from_be_bytes(val.as_ref().try_into().unwrap())))
+    }
+
+    pub(crate) fn due(&self, now: u64) -> Result<Vec<UserId>, Error> {
+        let mut due_users = Vec::new();
+        for entry in self.forget_requests.iter() {
+            let (key, value) = entry?;
+            if u64::from_be_bytes(value.as_ref().try_into().unwrap()) <= now {
+                due_users.push(UserId(u64::from_be_bytes(key.as_ref().try_into().unwrap())));
+            }
+        }
+        Ok(due_users)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HistoryKind {
+    /// A nickname set through this app
+    Rename,
+    /// A nickname recorded by /renamer admin import_current, set before the app was used
+    Baseline,
+    /// A nickname change made directly in Discord rather than through this app
+    External,
+}
+
+/// A member's nickname at some point in time and how it got there
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct HistoryEntry {
+    pub(crate) id: u64,
+    pub(crate) guild_id: u64,
+    pub(crate) user_id: u64,
+    /// Who set the nickname, if it was set through this app
+    pub(crate) actor_id: Option<u64>,
+    pub(crate) previous_nickname: Option<String>,
+    pub(crate) nickname: Option<String>,
+    pub(crate) timestamp: u64,
+    pub(crate) kind: HistoryKind,
+}
+
+/// Nickname history, keyed by guild, then member, then entry ID so that a member's
+/// entries can be prefix-scanned in chronological order
+pub(crate) struct HistoryDb {
+    ids: sled::Db,
+    history: sled::Tree,
+}
+
+impl HistoryDb {
+    /// Stores the entry under a newly generated ID, ignoring the one it was given, and
+    /// returns the ID
+    pub(crate) fn insert(&self, mut entry: HistoryEntry) -> Result<u64, Error> {
+        entry.id = self.ids.generate_id()?;
+        self.history
+            .insert(Self::key(&entry), serde_json::to_vec(&entry)?)?;
+        Ok(entry.id)
+    }
+
+    pub(crate) fn for_member(
+        &self,
+        guild_id: &GuildId,
+        user_id: &UserId,
+    ) -> Result<Vec<HistoryEntry>, Error> {
+        self.history
+            .scan_prefix(pair_key(guild_id.0, user_id.0))
+            .values()
+            .map(|value| Ok(serde_json::from_slice(&value?)?))
+            .collect()
+    }
+
+    pub(crate) fn get(&self, guild_id: &GuildId, id: u64) -> Result<Option<HistoryEntry>, Error> {
+        for value in self.history.scan_prefix(guild_id.0.to_be_bytes()).values() {
+            let entry: HistoryEntry = serde_json::from_slice(&value?)?;
+            if entry.id == id {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The latest entry for each member of the guild who has any
+    pub(crate) fn latest_for_guild(
+        &self,
+        guild_id: &GuildId,
+    ) -> Result<HashMap<u64, HistoryEntry>, Error> {
+        let mut latest = HashMap::new();
+        // Keys sort by member then entry ID, so later entries replace earlier ones
+        for value in self.history.scan_prefix(guild_id.0.to_be_bytes()).values() {
+            let entry: HistoryEntry = serde_json::from_slice(&value?)?;
+            latest.insert(entry.user_id, entry);
+        }
+        Ok(latest)
+    }
+
+    /// Every entry in any guild about the user or made by them
+    pub(crate) fn involving(&self, user_id: &UserId) -> Result<Vec<HistoryEntry>, Error> {
+        let mut entries = Vec::new();
+        for value in self.history.iter().values() {
+            let entry: HistoryEntry = serde_json::from_slice(&value?)?;
+            if entry.user_id == user_id.0 || entry.actor_id == Some(user_id.0) {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    pub(crate) fn remove_involving(&self, user_id: &UserId) -> Result<(), Error> {
+        for entry in self.involving(user_id)? {
+            self.history.remove(Self::key(&entry))?;
+        }
+        Ok(())
+    }
+
+    fn key(entry: &HistoryEntry) -> Vec<u8> {
+        let mut key = pair_key(entry.guild_id, entry.user_id).to_vec();
+        key.extend_from_slice(&entry.id.to_be_bytes());
+        key
+    }
+}
+
+/// Invocation and error counts per command across all guilds, with nothing identifying
+/// who ran them or where
+pub(crate) struct UsageDb {
+    command_stats: sled::Tree,
+}
+
+impl UsageDb {
+    pub(crate) fn record_invocation(&self, command: &str) -> Result<(), Error> {
+        self.increment(command, 0)
+    }
+
+    pub(crate) fn record_error(&self, command: &str) -> Result<(), Error> {
+        self.increment(command, 1)
+    }
+
+    /// Returns each command's name, invocation count and error count
+    pub(crate) fn report(&self) -> Result<Vec<(String, u64, u64)>, Error> {
+        self.command_stats
+            .iter()
+            .map(|item| {
+                let (key, value) = item?;
+                let counts = Self::counts(Some(&*value));
+                Ok((String::from_utf8(key.to_vec())?, counts[0], counts[1]))
+            })
+            .collect()
+    }
+
+    pub(crate) fn increment(&self, command: &str, index: usize) -> Result<(), Error> {
+        self.command_stats.update_and_fetch(command, |old| {
+            let mut counts = Self::counts(old);
+            counts[index] += 1;
+            Some(
+                counts
+                    .iter()
+                    .flat_map(|count| count.to_be_bytes())
+                    .collect::<Vec<u8>>(),
+            )
+        })?;
+        Ok(())
+    }
+
+    pub(crate) fn counts(value: Option<&[u8]>) -> [u64; 2] {
+        let mut counts = [0; 2];
+        if let Some(value) = value {
+            for (count, bytes) in counts.iter_mut().zip(value.chunks_exact(8)) {
+                *count = u64::from_be_bytes(bytes.try_into().unwrap());
+            }
+        }
+        counts
+    }
+}
+
+/// What a pending approval is waiting on
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum ApprovalKind {
+    /// A renamer renamed a staff member and a second renamer has to approve it
+    #[default]
+    StaffRename,
+    /// A member asked for a nickname with /renamer request and a renamer has to approve it
+    NicknameRequest,
+}
+
+/// A rename waiting for a renamer to approve it. For nickname requests, the actor is the
+/// member who asked.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct PendingApproval {
+    pub(crate) guild_id: u64,
+    pub(crate) actor_id: u64,
+    pub(crate) target_id: u64,
+    pub(crate) nickname: String,
+    /// Unix time after which the rename can no longer be approved
+    pub(crate) expires_at: u64,
+    /// Where the approval buttons were posted, so the message can be updated when the
+    /// request expires
+    #[serde(default)]
+    pub(crate) message: Option<(u64, u64)>,
+    #[serde(default)]
+    pub(crate) kind: ApprovalKind,
+}
+
+/// Pending staff renames and nickname requests, keyed by a generated ID that is also in
+/// the approval buttons
+pub(crate) struct StaffApprovalDb {
+    ids: sled::Db,
+    staff_approvals: sled::Tree,
+}
+
+impl StaffApprovalDb {
+    /// Stores the approval and returns its ID
+    pub(crate) fn insert(&self, approval: &PendingApproval) -> Result<u64, Error> {
+        let id = self.ids.generate_id()?;
+        self.staff_approvals
+            .insert(id.to_be_bytes(), serde_json::to_vec(approval)?)?;
+        Ok(id)
+    }
+
+    /// Remembers where the approval buttons were posted, unless the approval was already
+    /// reviewed
+    pub(crate) fn set_message(
+        &self,
+        id: u64,
+        channel_id: u64,
+        message_id: u64,
+    ) -> Result<(), Error> {
+        let Some(old) = self.staff_approvals.get(id.to_be_bytes())? else {
+            return Ok(());
+        };
+        let mut approval: PendingApproval = serde_json::from_slice(&old)?;
+        approval.message = Some((channel_id, message_id));
+        // Fails harmlessly if a reviewer took the approval in the meantime
+        let _ = self.staff_approvals.compare_and_swap(
+            id.to_be_bytes(),
+            Some(old),
+            Some(serde_json::to_vec(&approval)?),
+        )?;
+        Ok(())
+    }
+
+    /// Removes and returns every approval that expired by `now`
+    pub(crate) fn take_expired(&self, now: u64) -> Result<Vec<PendingApproval>, Error> {
+        let mut expired = Vec::new();
+        for item in self.staff_approvals.iter() {
+            let (key, value) = item?;
+            let pending: PendingApproval = serde_json::from_slice(&value)?;
+            if pending.expires_at <= now && self.staff_approvals.remove(key)?.is_some() {
+                expired.push(pending);
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Removes and returns the approval, so that it can only be acted on once
+    pub(crate) fn take(&self, id: u64) -> Result<Option<PendingApproval>, Error> {
+        match self.staff_approvals.remove(id.to_be_bytes())? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn get(&self, id: u64) -> Result<Option<PendingApproval>, Error> {
+        match self.staff_approvals.get(id.to_be_bytes())? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every pending approval in any guild asked for by the user or about them, with its ID
+    pub(crate) fn involving(&self, user_id: &UserId) -> Result<Vec<(u64, PendingApproval)>, Error> {
+        let mut approvals = Vec::new();
+        for item in self.staff_approvals.iter() {
+            let (key, value) = item?;
+            let approval: PendingApproval = serde_json::from_slice(&value)?;
+            if approval.actor_id == user_id.0 || approval.target_id == user_id.0 {
+                let id = u64::from_be_bytes(key.as_ref().try_into().unwrap());
+                approvals.push((id, approval));
+            }
+        }
+        Ok(approvals)
+    }
+
+    pub(crate) fn remove_involving(&self, user_id: &UserId) -> Result<(), Error> {
+        for (id, _) in self.involving(user_id)? {
+            self.staff_approvals.remove(id.to_be_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// A guild's settings, roles, target rules and disabled commands as they were just before a
+/// change
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ConfigSnapshot {
+    pub(crate) id: u64,
+    pub(crate) timestamp: u64,
+    /// Name of what was about to change
+    pub(crate) change: String,
+    pub(crate) settings: BTreeMap<String, String>,
+    /// Role names, in snapshots from before roles were stored by ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) renamer_role: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) allow_role: Option<String>,
+    #[serde(default)]
+    pub(crate) renamer_role_id: Option<u64>,
+    #[serde(default)]
+    pub(crate) allow_role_id: Option<u64>,
+    /// Protected role IDs by actor role ID
+    #[serde(default)]
+    pub(crate) target_rules: BTreeMap<u64, Vec<u64>>,
+    #[serde(default)]
+    pub(crate) disabled_commands: Vec<String>,
+}
+
+/// Earlier versions of each guild's configuration, keyed by guild then snapshot ID
+pub(crate) struct ConfigHistoryDb {
+    ids: sled::Db,
+    config_history: sled::Tree,
+}
+
+impl ConfigHistoryDb {
+    /// Stores the snapshot under a newly generated ID, ignoring the one it was given
+    fn insert(&self, guild_id: &GuildId, mut snapshot: ConfigSnapshot) -> Result<(), Error> {
+        snapshot.id = self.ids.generate_id()?;
+        self.config_history.insert(
+            Self::key(guild_id, snapshot.id),
+            serde_json::to_vec(&snapshot)?,
+        )?;
+        Ok(())
+    }
+
+    /// The guild's snapshots, oldest first
+    pub(crate) fn list(&self, guild_id: &GuildId) -> Result<Vec<ConfigSnapshot>, Error> {
+        self.config_history
+            .scan_prefix(guild_id.0.to_be_bytes())
+            .values()
+            .map(|value| Ok(serde_json::from_slice(&value?)?))
+            .collect()
+    }
+
+    pub(crate) fn get(&self, guild_id: &GuildId, id: u64) -> Result<Option<ConfigSnapshot>, Error> {
+        match self.config_history.get(Self::key(guild_id, id))? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn key(guild_id: &GuildId, id: u64) -> Vec<u8> {
+        let mut key = guild_id.0.to_be_bytes().to_vec();
+        key.extend_from_slice(&id.to_be_bytes());
+        key
+    }
+}
+
+/// A round of asking members without the Allow role to opt in. Only counts are kept,
+/// not who was asked or who answered.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ConsentCampaign {
+    pub(crate) id: u64,
+    pub(crate) guild_id: u64,
+    pub(crate) started_at: u64,
+    /// Members asked directly, or 0 when the invitation was posted in a channel
+    pub(crate) contacted: u64,
+    pub(crate) opted_in: u64,
+}
+
+/// Consent campaigns, keyed by guild then campaign ID
+pub(crate) struct ConsentCampaignDb {
+    ids: sled::Db,
+    consent_campaigns: sled::Tree,
+}
+
+impl ConsentCampaignDb {
+    /// Stores the campaign under a newly generated ID, ignoring the one it was given, and
+    /// returns the ID
+    pub(crate) fn insert(&self, mut campaign: ConsentCampaign) -> Result<u64, Error> {
+        campaign.id = self.ids.generate_id()?;
+        self.save(&campaign)?;
+        Ok(campaign.id)
+    }
+
+    fn save(&self, campaign: &ConsentCampaign) -> Result<(), Error> {
+        self.consent_campaigns.insert(
+            pair_key(campaign.guild_id, campaign.id),
+            serde_json::to_vec(campaign)?,
+        )?;
+        Ok(())
+    }
+
+    /// Finds a campaign by ID alone, since opt-in buttons sent by DM don't say which
+    /// guild they're from
+    pub(crate) fn get(&self, id: u64) -> Result<Option<ConsentCampaign>, Error> {
+        for value in self.consent_campaigns.iter().values() {
+            let campaign: ConsentCampaign = serde_json::from_slice(&value?)?;
+            if campaign.id == id {
+                return Ok(Some(campaign));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The guild's campaigns, oldest first
+    pub(crate) fn for_guild(&self, guild_id: &GuildId) -> Result<Vec<ConsentCampaign>, Error> {
+        self.consent_campaigns
+            .scan_prefix(guild_id.0.to_be_bytes())
+            .values()
+            .map(|value| Ok(serde_json::from_slice(&value?)?))
+            .collect()
+    }
+
+    pub(crate) fn record_opt_in(&self, id: u64) -> Result<(), Error> {
+        self.update(id, |campaign| campaign.opted_in += 1)
+    }
+
+    pub(crate) fn record_contacted(&self, id: u64) -> Result<(), Error> {
+        self.update(id, |campaign| campaign.contacted += 1)
+    }
+
+    /// Changes a stored campaign, retrying if another change to it lands first
+    fn update(&self, id: u64, change: impl Fn(&mut ConsentCampaign)) -> Result<(), Error> {
+        let Some(campaign) = self.get(id)? else {
+            return Ok(());
+        };
+        let key = pair_key(campaign.guild_id, campaign.id);
+        loop {
+            let Some(old) = self.consent_campaigns.get(key)? else {
+                return Ok(());
+            };
+            let mut campaign: ConsentCampaign = serde_json::from_slice(&old)?;
+            change(&mut campaign);
+            let new = serde_json::to_vec(&campaign)?;
+            if self
+                .consent_campaigns
+                .compare_and_swap(key, Some(old), Some(new))?
+                .is_ok()
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Commands admins have turned off in their guild, keyed by guild and qualified name
+pub(crate) struct DisabledCommandDb {
+    disabled_commands: sled::Tree,
+}
+
+impl DisabledCommandDb {
+    pub(crate) fn contains(&self, guild_id: &GuildId, command: &str) -> Result<bool, Error> {
+        Ok(self
+            .disabled_commands
+            .contains_key(Self::key(guild_id, command))?)
+    }
+
+    /// Returns whether the command was already disabled. Doesn't record the change in the
+    /// config history; see [`Storage::disable_command`].
+    fn insert(&self, guild_id: &GuildId, command: &str) -> Result<bool, Error> {
+        let prev_val = self
+            .disabled_commands
+            .insert(Self::key(guild_id, command), &[])?;
+        Ok(prev_val.is_some())
+    }
+
+    /// Returns whether the command was disabled. Doesn't record the change in the config
+    /// history; see [`Storage::enable_command`].
+    fn remove(&self, guild_id: &GuildId, command: &str) -> Result<bool, Error> {
+        let prev_val = self
+            .disabled_commands
+            .remove(Self::key(guild_id, command))?;
+        Ok(prev_val.is_some())
+    }
+
+    pub(crate) fn list(&self, guild_id: &GuildId) -> Result<Vec<String>, Error> {
+        self.disabled_commands
+            .scan_prefix(guild_id.0.to_be_bytes())
+            .keys()
+            .map(|key| Ok(String::from_utf8(key?[8..].to_vec())?))
+            .collect()
+    }
+
+    /// Replaces all of the guild's disabled commands without recording the change in the
+    /// config history
+    pub(crate) fn restore(&self, guild_id: &GuildId, commands: &[String]) -> Result<(), Error> {
+        for key in self
+            .disabled_commands
+            .scan_prefix(guild_id.0.to_be_bytes())
+            .keys()
+        {
+            self.disabled_commands.remove(key?)?;
+        }
+        for command in commands {
+            self.insert(guild_id, command)?;
+        }
+        Ok(())
+    }
+
+    fn key(guild_id: &GuildId, command: &str) -> Vec<u8> {
+        let mut key = guild_id.0.to_be_bytes().to_vec();
+        key.extend_from_slice(command.as_bytes());
+        key
+    }
+}
+
+/// The schema version of the databases and when each migration to it was applied
+pub(crate) struct MigrationDb {
+    data: sled::Db,
+    migrations: sled::Tree,
+}
+
+impl MigrationDb {
+    pub(crate) fn version(&self) -> Result<u64, Error> {
+        migrations::schema_version(&self.data)
+    }
+
+    /// Each applied migration's version and when it was applied, oldest first. Versions
+    /// a new install started at aren't listed, since nothing was migrated.
+    pub(crate) fn applied(&self) -> Result<Vec<(u64, u64)>, Error> {
+        self.migrations
+            .iter()
+            .map(|item| {
+                let (key, value) = item?;
+                Ok((
+                    u64::from_be_bytes(key.as_ref().try_into().unwrap()),
+                    u64::from_be_bytes(value.as_ref().try_into().unwrap()),
+                ))
+            })
+            .collect()
+    }
+}
+
+#[derive(Display, Clone, Copy)]
+pub(crate) enum Setting {
+    AutoTransliterate,
+    RandomSeedWords,
+    RandomMinSyllables,
+    RandomMaxSyllables,
+    MatchThreshold,
+    SelfService,
+    ValidationPipeline,
+    StaffRoles,
+    BoosterPerkTier,
+    MemberLookup,
+    MinAccountAgeHours,
+    MinMemberAgeHours,
+    SweepChannel,
+    SweepAutoFix,
+    ObserveMode,
+    /// Unix time the guild was last swept, kept by the app rather than configured
+    LastSweep,
+}
+
+#[derive(Display, Clone, Copy)]
+pub(crate) enum AppRole {
+    Renamer,
+    Allow,
+}
+
+impl AppRole {
+    /// Name to give the role when an admin doesn't pick one, in the server's language
+    pub(crate) fn default_name(&self, locale: &str) -> &'static str {
+        let language = locale.split('-').next().unwrap_or(locale);
+        match (self, language) {
+            (Renamer, "de") => "Umbenenner",
+            (Allow, "de") => "Umbenennbar",
+            (Renamer, "es") => "Renombrador",
+            (Allow, "es") => "Renombrable",
+            (Renamer, "fr") => "Renommeur",
+            (Allow, "fr") => "Renommable",
+            (Renamer, "it") => "Rinominatore",
+            (Allow, "it") => "Rinominabile",
+            (Renamer, "nl") => "Hernoemer",
+            (Allow, "nl") => "Hernoembaar",
+            (Renamer, "pt") => "Renomeador",
+            (Allow, "pt") => "Renomeável",
+            (Renamer, _) => "Renamer",
+            (Allow, _) => "Renamable",
+        }
+    }
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
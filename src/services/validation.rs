@@ -0,0 +1,444 @@
+//! Configurable per-guild nickname validation
+//!
+//! Each guild has a [`Pipeline`] of validation steps run in order. Every step can be
+//! turned off, only warn, strip the offending characters, or reject the nickname outright.
+
+use poise::serenity_prelude::GuildId;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use super::storage::Setting::ValidationPipeline;
+use super::storage::Storage;
+use crate::commands::Error;
+use crate::transform::is_latin;
+
+/// Discord's nickname length limit, in Unicode scalar values
+const MAX_NICKNAME_LEN: usize = 32;
+
+#[derive(poise::ChoiceParameter, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Step {
+    #[name = "length"]
+    Length,
+    #[name = "blocklist"]
+    Blocklist,
+    #[name = "regex"]
+    Regex,
+    #[name = "anti_hoist"]
+    AntiHoist,
+    #[name = "zero_width"]
+    ZeroWidth,
+    #[name = "emoji"]
+    Emoji,
+    #[name = "script"]
+    Script,
+}
+
+impl Step {
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            Step::Length => "length",
+            Step::Blocklist => "blocklist",
+            Step::Regex => "regex",
+            Step::AntiHoist => "anti_hoist",
+            Step::ZeroWidth => "zero_width",
+            Step::Emoji => "emoji",
+            Step::Script => "script",
+        }
+    }
+}
+
+#[derive(poise::ChoiceParameter, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mode {
+    #[name = "off"]
+    Off,
+    #[name = "warn"]
+    Warn,
+    #[name = "strip"]
+    Strip,
+    #[name = "reject"]
+    Reject,
+}
+
+impl Mode {
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            Mode::Off => "off",
+            Mode::Warn => "warn",
+            Mode::Strip => "strip",
+            Mode::Reject => "reject",
+        }
+    }
+}
+
+/// What a single step did to a nickname
+pub(crate) enum Outcome {
+    /// The step is turned off
+    Off,
+    Passed,
+    Warned(String),
+    Stripped(String),
+    Rejected(String),
+}
+
+/// The result of running a nickname through a pipeline
+pub(crate) struct Report {
+    /// The nickname after any stripping
+    pub(crate) nickname: String,
+    pub(crate) results: Vec<(Step, Outcome)>,
+}
+
+impl Report {
+    /// Why the nickname can't be used, if it can't
+    pub(crate) fn rejection(&self) -> Option<&str> {
+        let rejected = self.results.iter().find_map(|(_, outcome)| match outcome {
+            Outcome::Rejected(problem) => Some(problem.as_str()),
+            _ => None,
+        });
+        if rejected.is_none() && self.nickname.trim().is_empty() {
+            return Some("nothing is left of it");
+        }
+        rejected
+    }
+
+    pub(crate) fn warnings(&self) -> Vec<&str> {
+        self.results
+            .iter()
+            .filter_map(|(_, outcome)| match outcome {
+                Outcome::Warned(problem) => Some(problem.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct Pipeline {
+    /// Steps in the order they run
+    pub(crate) steps: Vec<(Step, Mode)>,
+    pub(crate) blocked_words: Vec<String>,
+    pub(crate) regex_rules: Vec<String>,
+}
+
+impl Default for Pipeline {
+    /// Only enforces Discord's own length limit, with stripping steps ordered before it
+    fn default() -> Self {
+        Self {
+            steps: vec![
+                (Step::ZeroWidth, Mode::Off),
+                (Step::Blocklist, Mode::Off),
+                (Step::Regex, Mode::Off),
+                (Step::AntiHoist, Mode::Off),
+                (Step::Emoji, Mode::Off),
+                (Step::Script, Mode::Off),
+                (Step::Length, Mode::Reject),
+            ],
+            blocked_words: Vec::new(),
+            regex_rules: Vec::new(),
+        }
+    }
+}
+
+impl Pipeline {
+    pub(crate) fn set_mode(&mut self, step: Step, mode: Mode) {
+        for (s, m) in &mut self.steps {
+            if *s == step {
+                *m = mode;
+            }
+        }
+    }
+
+    /// Moves a step to a zero-based position in the pipeline
+    pub(crate) fn move_step(&mut self, step: Step, position: usize) {
+        if let Some(index) = self.steps.iter().position(|(s, _)| *s == step) {
+            let entry = self.steps.remove(index);
+            self.steps.insert(position.min(self.steps.len()), entry);
+        }
+    }
+
+    /// Lists the steps in order with their modes, then the blocklist and regex rules
+    pub(crate) fn describe(&self) -> String {
+        let mut lines: Vec<String> = self
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(i, (step, mode))| format!("{}. {}: {}", i + 1, step.label(), mode.label()))
+            .collect();
+        let list = |items: &[String]| {
+            if items.is_empty() {
+                "none".to_string()
+            } else {
+                items
+                    .iter()
+                    .map(|item| format!("`{}`", item))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        };
+        lines.push(format!("Blocked words: {}", list(&self.blocked_words)));
+        lines.push(format!("Regex rules: {}", list(&self.regex_rules)));
+        lines.join("\n")
+    }
+
+    pub(crate) fn validate(&self, nickname: &str) -> Report {
+        let mut nickname = nickname.to_string();
+        let mut results = Vec::new();
+
+        for (step, mode) in &self.steps {
+            let outcome = match (mode, self.check(*step, &nickname)) {
+                (Mode::Off, _) => Outcome::Off,
+                (_, None) => Outcome::Passed,
+                (Mode::Warn, Some((problem, _))) => Outcome::Warned(problem),
+                (Mode::Strip, Some((problem, stripped))) => {
+                    nickname = stripped;
+                    Outcome::Stripped(problem)
+                }
+                (Mode::Reject, Some((problem, _))) => Outcome::Rejected(problem),
+            };
+            results.push((*step, outcome));
+        }
+
+        Report { nickname, results }
+    }
+
+    /// Checks a nickname against one step, returning the problem found and the nickname
+    /// with the offending parts removed
+    fn check(&self, step: Step, nickname: &str) -> Option<(String, String)> {
+        match step {
+            Step::Length => {
+                // "Nicknames must be between 1 and 32 characters long."
+                // Trims leading and trailing whitespace but does not trim internal whitespace.
+                // Discord counts Unicode scalar values, not bytes or grapheme clusters, so an
+                // emoji is one character but a letter with a combining accent is two.
+                let trimmed = nickname.trim();
+                match trimmed.chars().count() {
+                    0 => Some(("is empty".into(), String::new())),
+                    33.. => Some((
+                        format!("is longer than {} characters", MAX_NICKNAME_LEN),
+                        trimmed.chars().take(MAX_NICKNAME_LEN).collect(),
+                    )),
+                    _ => None,
+                }
+            }
+            Step::Blocklist => {
+                // Whole words only, so blocking "cock" leaves Hancock alone
+                let words: Vec<(&String, Regex)> = self
+                    .blocked_words
+                    .iter()
+                    .filter_map(|word| {
+                        let rule = format!(r"\b{}\b", regex::escape(word));
+                        Some((word, case_insensitive(&rule)?))
+                    })
+                    .collect();
+                let (blocked_word, _) = words.iter().find(|(_, re)| re.is_match(nickname))?;
+                let stripped = words.iter().fold(nickname.to_string(), |name, (_, re)| {
+                    re.replace_all(&name, "").into_owned()
+                });
+                Some((
+                    format!("contains the blocked word {}", blocked_word),
+                    stripped,
+                ))
+            }
+            Step::Regex => {
+                let rules: Vec<(&String, Regex)> = self
+                    .regex_rules
+                    .iter()
+                    .filter_map(|rule| Some((rule, case_insensitive(rule)?)))
+                    .collect();
+                let (matched_rule, _) = rules.iter().find(|(_, re)| re.is_match(nickname))?;
+                let stripped = rules.iter().fold(nickname.to_string(), |name, (_, re)| {
+                    re.replace_all(&name, "").into_owned()
+                });
+                Some((format!("matches the rule {}", matched_rule), stripped))
+            }
+            Step::AntiHoist => {
+                let first = nickname.trim_start().chars().next()?;
+                if !first.is_ascii_punctuation() {
+                    return None;
+                }
+                let stripped = nickname
+                    .trim_start_matches(|c: char| c.is_ascii_punctuation() || c.is_whitespace())
+                    .to_string();
+                Some((
+                    format!("starts with {}, which hoists it up the member list", first),
+                    stripped,
+                ))
+            }
+            Step::ZeroWidth => strip_chars(nickname, is_zero_width, "invisible characters"),
+            Step::Emoji => strip_chars(nickname, is_emoji, "emoji"),
+            Step::Script => strip_chars(
+                nickname,
+                |c| c.is_alphabetic() && !is_latin(c),
+                "non-Latin letters",
+            ),
+        }
+    }
+}
+
+/// Compiles a rule as a case-insensitive regex, or returns `None` if it isn't valid
+pub(crate) fn case_insensitive(rule: &str) -> Option<Regex> {
+    RegexBuilder::new(rule).case_insensitive(true).build().ok()
+}
+
+fn strip_chars(
+    nickname: &str,
+    offending: impl Fn(char) -> bool,
+    description: &str,
+) -> Option<(String, String)> {
+    if !nickname.chars().any(&offending) {
+        return None;
+    }
+    let stripped = nickname.chars().filter(|c| !offending(*c)).collect();
+    Some((format!("contains {}", description), stripped))
+}
+
+fn is_zero_width(c: char) -> bool {
+    matches!(c,
+        '\u{00AD}'
+        | '\u{034F}'
+        | '\u{061C}'
+        | '\u{115F}'..='\u{1160}'
+        | '\u{17B4}'..='\u{17B5}'
+        | '\u{180E}'
+        | '\u{200B}'..='\u{200F}'
+        | '\u{202A}'..='\u{202E}'
+        | '\u{2060}'..='\u{206F}'
+        | '\u{3164}'
+        | '\u{FEFF}'
+        | '\u{FFA0}'
+    )
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(c,
+        '\u{2600}'..='\u{27BF}'
+        | '\u{2B00}'..='\u{2BFF}'
+        | '\u{FE0F}'
+        | '\u{1F000}'..='\u{1FAFF}'
+    )
+}
+
+/// The guild's pipeline, or the default one if it never configured validation
+pub(crate) fn load_pipeline(storage: &Storage, guild_id: &GuildId) -> Result<Pipeline, Error> {
+    Ok(match storage.settings.get(ValidationPipeline, guild_id)? {
+        Some(json) => serde_json::from_str(&json)?,
+        None => Pipeline::default(),
+    })
+}
+
+/// Runs a nickname through the guild's pipeline
+pub(crate) fn validate_nickname(
+    storage: &Storage,
+    guild_id: &GuildId,
+    nickname: &str,
+) -> Result<Report, Error> {
+    Ok(load_pipeline(storage, guild_id)?.validate(nickname))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_valid_nickname(nickname: &str) -> bool {
+        Pipeline::default().validate(nickname).rejection().is_none()
+    }
+
+    #[test]
+    fn nickname_length_limits() {
+        assert!(!is_valid_nickname(""));
+        assert!(!is_valid_nickname("   "));
+        assert!(is_valid_nickname("a"));
+        assert!(is_valid_nickname(&"a".repeat(32)));
+        assert!(!is_valid_nickname(&"a".repeat(33)));
+    }
+
+    #[test]
+    fn nickname_length_ignores_surrounding_whitespace() {
+        assert!(is_valid_nickname(&format!("  {}  ", "a".repeat(32))));
+        assert!(is_valid_nickname("a b"));
+    }
+
+    #[test]
+    fn nickname_length_counts_emoji_as_single_characters() {
+        // 20 emoji are 80 bytes of UTF-8 but only 20 characters
+        assert!(is_valid_nickname(&"😀".repeat(20)));
+        assert!(is_valid_nickname(&"😀".repeat(32)));
+        assert!(!is_valid_nickname(&"😀".repeat(33)));
+    }
+
+    #[test]
+    fn nickname_length_counts_cjk_as_single_characters() {
+        assert!(is_valid_nickname(&"名".repeat(32)));
+        assert!(!is_valid_nickname(&"名".repeat(33)));
+    }
+
+    #[test]
+    fn nickname_length_counts_combining_marks_separately() {
+        // "e" followed by U+0301 COMBINING ACUTE ACCENT is two scalar values
+        let accented = "e\u{301}";
+        assert!(is_valid_nickname(&accented.repeat(16)));
+        assert!(!is_valid_nickname(&format!("{}a", accented.repeat(16))));
+    }
+
+    #[test]
+    fn strip_mode_feeds_later_steps() {
+        let mut pipeline = Pipeline::default();
+        pipeline.set_mode(Step::ZeroWidth, Mode::Strip);
+        pipeline.set_mode(Step::AntiHoist, Mode::Strip);
+
+        let report = pipeline.validate("\u{200B}!!Dave");
+        assert_eq!(report.nickname, "Dave");
+        assert!(report.rejection().is_none());
+    }
+
+    #[test]
+    fn warn_mode_keeps_nickname() {
+        let mut pipeline = Pipeline::default();
+        pipeline.set_mode(Step::Emoji, Mode::Warn);
+
+        let report = pipeline.validate("Dave 😀");
+        assert_eq!(report.nickname, "Dave 😀");
+        assert_eq!(report.warnings(), vec!["contains emoji"]);
+        assert!(report.rejection().is_none());
+    }
+
+    #[test]
+    fn blocklist_and_regex_are_case_insensitive() {
+        let mut pipeline = Pipeline::default();
+        pipeline.blocked_words.push("bad".into());
+        pipeline.regex_rules.push("^admin".into());
+        pipeline.set_mode(Step::Blocklist, Mode::Reject);
+        pipeline.set_mode(Step::Regex, Mode::Strip);
+
+        assert!(pipeline.validate("Very BAD name").rejection().is_some());
+        assert_eq!(pipeline.validate("ADMIN Dave").nickname, " Dave");
+    }
+
+    #[test]
+    fn blocklist_matches_whole_words_only() {
+        let mut pipeline = Pipeline::default();
+        pipeline.blocked_words.push("cock".into());
+        pipeline.set_mode(Step::Blocklist, Mode::Strip);
+
+        assert_eq!(pipeline.validate("Hancock").nickname, "Hancock");
+        assert_eq!(pipeline.validate("Peacock fan").nickname, "Peacock fan");
+        assert_eq!(pipeline.validate("Dave Cock").nickname, "Dave ");
+    }
+
+    #[test]
+    fn stripping_everything_rejects() {
+        let mut pipeline = Pipeline::default();
+        pipeline.set_mode(Step::Emoji, Mode::Strip);
+        pipeline.set_mode(Step::Length, Mode::Off);
+
+        assert!(pipeline.validate("😀😀").rejection().is_some());
+    }
+
+    #[test]
+    fn steps_can_be_reordered() {
+        let mut pipeline = Pipeline::default();
+        pipeline.move_step(Step::Length, 0);
+        assert!(pipeline.steps[0].0 == Step::Length);
+        pipeline.move_step(Step::Length, 100);
+        assert!(pipeline.steps.last().unwrap().0 == Step::Length);
+    }
+}
@@ -0,0 +1,29 @@
+//! Transformations applied to nicknames before they are set
+
+/// Replaces letters from non-Latin scripts (Cyrillic, Greek, CJK, ...) with a Latin
+/// approximation, leaving Latin letters, digits, symbols and emoji untouched
+pub(crate) fn transliterate(nickname: &str) -> String {
+    let transliterated: String = nickname
+        .chars()
+        .map(|c| match deunicode::deunicode_char(c) {
+            Some(latin) if c.is_alphabetic() && !is_latin(c) => latin.to_string(),
+            _ => c.to_string(),
+        })
+        .collect();
+
+    // CJK characters transliterate to space-separated syllables
+    transliterated
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub(crate) fn is_latin(c: char) -> bool {
+    matches!(c,
+        '\u{0000}'..='\u{024F}'
+        | '\u{1E00}'..='\u{1EFF}'
+        | '\u{2C60}'..='\u{2C7F}'
+        | '\u{A720}'..='\u{A7FF}'
+        | '\u{AB30}'..='\u{AB6F}'
+    )
+}